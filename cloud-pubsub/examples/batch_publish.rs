@@ -0,0 +1,36 @@
+use cloud_pubsub::{Client, EncodedMessage, PublisherConfig};
+use serde_derive::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct Config {
+    topic: String,
+    google_application_credentials: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let config: Config = envy::from_env().expect("ENV is not valid");
+
+    let pubsub = Client::new(config.google_application_credentials)
+        .await
+        .expect("Failed to initialize pubsub");
+
+    let publisher = pubsub.topic(config.topic).publisher(PublisherConfig {
+        max_messages: 50,
+        max_delay: Duration::from_millis(50),
+        ..PublisherConfig::default()
+    });
+
+    // Every message is queued before awaiting, so they go out in two requests.
+    let results: Vec<_> = (0..100)
+        .map(|i| publisher.publish(EncodedMessage::new(&i, None, None)))
+        .collect();
+
+    for result in results {
+        match result.await {
+            Ok(id) => println!("Published {}", id),
+            Err(e) => eprintln!("Failed sending message {}", e),
+        }
+    }
+}
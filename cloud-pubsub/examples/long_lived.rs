@@ -1,9 +1,10 @@
 use cloud_pubsub::error;
-use cloud_pubsub::{Client, EncodedMessage, FromPubSubMessage, Subscription};
+use cloud_pubsub::{
+    CancellationToken, Client, EncodedMessage, FromPubSubMessage, SubscriberConfig,
+};
 use serde_derive::Deserialize;
-use std::sync::Arc;
 use std::time::Duration;
-use tokio::{signal, task};
+use tokio::signal;
 
 #[derive(Deserialize)]
 struct Config {
@@ -11,6 +12,7 @@ struct Config {
     google_application_credentials: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 struct UpdatePacket(String);
 
@@ -23,31 +25,6 @@ impl FromPubSubMessage for UpdatePacket {
     }
 }
 
-fn schedule_pubsub_pull(subscription: Arc<Subscription>) {
-    task::spawn(async move {
-        while subscription.client().is_running() {
-            match subscription.get_messages::<UpdatePacket>(100).await {
-                Ok(messages) => {
-                    for (result, ack_id) in messages {
-                        match result {
-                            Ok(message) => {
-                                println!("Received: {:?}", message);
-                                let subscription = Arc::clone(&subscription);
-                                task::spawn(async move {
-                                    subscription.acknowledge_messages(vec![ack_id]).await;
-                                });
-                            }
-                            Err(e) => log::error!("Failed converting to UpdatePacket: {}", e),
-                        }
-                    }
-                }
-                Err(e) => eprintln!("Failed to pull PubSub messages: {}", e),
-            }
-        }
-        println!("No longer pulling");
-    });
-}
-
 #[tokio::main]
 async fn main() -> Result<(), error::Error> {
     let parsed_env = envy::from_env::<Config>();
@@ -59,34 +36,35 @@ async fn main() -> Result<(), error::Error> {
 
     let pubsub = match Client::new(config.google_application_credentials).await {
         Err(e) => panic!("Failed to initialize pubsub: {}", e),
-        Ok(mut client) => {
-            if let Err(e) = client.refresh_token().await {
-                eprintln!("Failed to get token: {}", e);
-            } else {
-                println!("Got fresh token");
-            }
-            Arc::new(client)
-        }
+        Ok(client) => client,
     };
 
     pubsub.spawn_token_renew(Duration::from_secs(15 * 60));
 
-    let topic = Arc::new(pubsub.topic(config.topic));
+    let topic = pubsub.topic(config.topic);
     let subscription = topic.subscribe().await?;
     println!("Subscribed to topic with: {}", subscription.name);
-    let sub = Arc::new(subscription);
-    schedule_pubsub_pull(Arc::clone(&sub));
-    signal::ctrl_c().await?;
-    println!("Cleaning up");
+
+    let cancel = CancellationToken::new();
+    let shutdown = cancel.clone();
+    tokio::spawn(async move {
+        let _ = signal::ctrl_c().await;
+        println!("Cleaning up");
+        shutdown.cancel();
+    });
+
+    subscription
+        .subscriber(SubscriberConfig::default())
+        .run(cancel, |message: UpdatePacket| async move {
+            println!("Received: {:?}", message);
+            Ok::<(), error::Error>(())
+        })
+        .await;
+    println!("No longer pulling");
+
     pubsub.stop();
-    println!("Waiting for current Pull to finish....");
-    while Arc::strong_count(&sub) > 1 {}
     println!("Deleting subscription");
-    if let Ok(s) = Arc::try_unwrap(sub) {
-        s.destroy().await?;
-        println!("Successfully deleted subscription");
-    } else {
-        eprintln!("Subscription was still ownded");
-    }
+    subscription.destroy().await?;
+    println!("Successfully deleted subscription");
     Ok(())
 }
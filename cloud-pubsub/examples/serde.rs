@@ -10,6 +10,7 @@ struct Config {
     google_application_credentials: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct UpdatePacket {
     id: u64,
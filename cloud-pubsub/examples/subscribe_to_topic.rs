@@ -8,6 +8,7 @@ struct Config {
     google_application_credentials: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 struct UpdatePacket(String);
 
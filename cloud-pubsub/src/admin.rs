@@ -0,0 +1,655 @@
+use crate::client::Client;
+use crate::error;
+use crate::subscription::Subscription;
+use crate::topic::Topic;
+use hyper::Method;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Settings of a topic, unset fields keep the server defaults.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<HashMap<String, String>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "duration_string"
+    )]
+    pub message_retention_duration: Option<Duration>,
+}
+
+/// Settings of a subscription, unset fields keep the server defaults.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ack_deadline_seconds: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retain_acked_messages: Option<bool>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "duration_string"
+    )]
+    pub message_retention_duration: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dead_letter_policy: Option<DeadLetterPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_policy: Option<SubscriptionRetryPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_message_ordering: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration_policy: Option<ExpirationPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterPolicy {
+    /// Full name of the topic, `projects/{project}/topics/{topic}`.
+    pub dead_letter_topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_delivery_attempts: Option<i32>,
+}
+
+/// Redelivery delays of the server, not to be confused with [`crate::RetryPolicy`] which
+/// retries the client requests.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionRetryPolicy {
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "duration_string"
+    )]
+    pub minimum_backoff: Option<Duration>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "duration_string"
+    )]
+    pub maximum_backoff: Option<Duration>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ExpirationPolicy {
+    /// Inactivity period after which the subscription is deleted, `None` never expires.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "duration_string"
+    )]
+    pub ttl: Option<Duration>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicPage {
+    #[serde(default)]
+    pub topics: Vec<Topic>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionPage {
+    #[serde(default)]
+    pub subscriptions: Vec<Subscription>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateRequest<T> {
+    #[serde(rename = "topic", skip_serializing_if = "Option::is_none")]
+    topic: Option<T>,
+    #[serde(rename = "subscription", skip_serializing_if = "Option::is_none")]
+    subscription: Option<T>,
+    update_mask: String,
+}
+
+impl Client {
+    pub async fn create_topic(
+        &self,
+        name: String,
+        config: TopicConfig,
+    ) -> Result<Topic, error::Error> {
+        let mut topic = self.topic(name);
+        topic.config = config;
+        let json = serde_json::to_string(&topic)?;
+        self.admin_request(Method::PUT, &topic.name, json).await
+    }
+
+    pub async fn get_topic(&self, name: String) -> Result<Topic, error::Error> {
+        let name = self.topic(name).name;
+        self.admin_request(Method::GET, &name, String::new()).await
+    }
+
+    /// Changes the fields set in `config`, the others are left untouched.
+    pub async fn update_topic(
+        &self,
+        name: String,
+        config: TopicConfig,
+    ) -> Result<Topic, error::Error> {
+        let name = self.topic(name).name;
+        let (config, update_mask) = with_update_mask(&config)?;
+        let json = serde_json::to_string(&UpdateRequest {
+            topic: Some(config),
+            subscription: None,
+            update_mask,
+        })?;
+        self.admin_request(Method::PATCH, &name, json).await
+    }
+
+    /// Lists a page of the project topics, pass the `next_page_token` of a page to get the
+    /// following one.
+    pub async fn list_topics(
+        &self,
+        page_size: Option<i32>,
+        page_token: Option<String>,
+    ) -> Result<TopicPage, error::Error> {
+        let path = format!(
+            "projects/{}/topics{}",
+            self.project(),
+            page_query(page_size, page_token)
+        );
+        let mut page: TopicPage = self
+            .admin_request(Method::GET, &path, String::new())
+            .await?;
+        for topic in &mut page.topics {
+            topic.client = Some(self.clone());
+        }
+        Ok(page)
+    }
+
+    pub async fn delete_topic(&self, name: String) -> Result<(), error::Error> {
+        let name = self.topic(name).name;
+        self.admin_request::<serde_json::Value>(Method::DELETE, &name, String::new())
+            .await
+            .map(|_| ())
+    }
+
+    /// Creates a subscription to `topic`, both names being relative to the client project.
+    pub async fn create_subscription(
+        &self,
+        name: String,
+        topic: String,
+        config: SubscriptionConfig,
+    ) -> Result<Subscription, error::Error> {
+        let mut subscription = self.subscribe(name);
+        subscription.topic = Some(self.topic(topic).name);
+        subscription.config = config;
+        let json = serde_json::to_string(&subscription)?;
+        self.admin_request(Method::PUT, &subscription.name, json)
+            .await
+    }
+
+    pub async fn get_subscription(&self, name: String) -> Result<Subscription, error::Error> {
+        let name = self.subscribe(name).name;
+        self.admin_request(Method::GET, &name, String::new()).await
+    }
+
+    /// Changes the fields set in `config`, the others are left untouched.
+    pub async fn update_subscription(
+        &self,
+        name: String,
+        config: SubscriptionConfig,
+    ) -> Result<Subscription, error::Error> {
+        let name = self.subscribe(name).name;
+        let (config, update_mask) = with_update_mask(&config)?;
+        let json = serde_json::to_string(&UpdateRequest {
+            topic: None,
+            subscription: Some(config),
+            update_mask,
+        })?;
+        self.admin_request(Method::PATCH, &name, json).await
+    }
+
+    /// Lists a page of the project subscriptions, pass the `next_page_token` of a page to get
+    /// the following one.
+    pub async fn list_subscriptions(
+        &self,
+        page_size: Option<i32>,
+        page_token: Option<String>,
+    ) -> Result<SubscriptionPage, error::Error> {
+        let path = format!(
+            "projects/{}/subscriptions{}",
+            self.project(),
+            page_query(page_size, page_token)
+        );
+        let mut page: SubscriptionPage = self
+            .admin_request(Method::GET, &path, String::new())
+            .await?;
+        for subscription in &mut page.subscriptions {
+            subscription.client = Some(self.clone());
+        }
+        Ok(page)
+    }
+
+    pub async fn delete_subscription(&self, name: String) -> Result<(), error::Error> {
+        self.subscribe(name).destroy().await
+    }
+
+    async fn admin_request<T: DeserializeOwned + Attach>(
+        &self,
+        method: Method,
+        path: &str,
+        json: String,
+    ) -> Result<T, error::Error> {
+        let body = self.perform(method, path, json).await?;
+        let mut resource: T = serde_json::from_slice(&body)?;
+        resource.attach(self);
+        Ok(resource)
+    }
+}
+
+/// Gives the client to the resources returned by the API.
+trait Attach {
+    fn attach(&mut self, _client: &Client) {}
+}
+
+impl Attach for Topic {
+    fn attach(&mut self, client: &Client) {
+        self.client = Some(client.clone());
+    }
+}
+
+impl Attach for Subscription {
+    fn attach(&mut self, client: &Client) {
+        self.client = Some(client.clone());
+    }
+}
+
+impl Attach for TopicPage {}
+impl Attach for SubscriptionPage {}
+impl Attach for serde_json::Value {}
+
+fn page_query(page_size: Option<i32>, page_token: Option<String>) -> String {
+    let mut params = Vec::new();
+    if let Some(size) = page_size {
+        params.push(format!("pageSize={}", size));
+    }
+    if let Some(token) = page_token {
+        params.push(format!("pageToken={}", encode_query_value(&token)));
+    }
+    if params.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", params.join("&"))
+    }
+}
+
+pub(crate) fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// The update mask lists the fields present in the serialized config.
+fn with_update_mask<T: serde::Serialize>(
+    config: &T,
+) -> Result<(serde_json::Value, String), error::Error> {
+    let value = serde_json::to_value(config)?;
+    let update_mask = value
+        .as_object()
+        .map(|fields| fields.keys().cloned().collect::<Vec<_>>().join(","))
+        .unwrap_or_default();
+    Ok((value, update_mask))
+}
+
+/// Durations as the `"3.5s"` strings of the Google APIs.
+mod duration_string {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(d) if d.subsec_nanos() == 0 => {
+                serializer.serialize_str(&format!("{}s", d.as_secs()))
+            }
+            Some(d) => {
+                serializer.serialize_str(&format!("{}.{:09}s", d.as_secs(), d.subsec_nanos()))
+            }
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        let value = match Option::<String>::deserialize(deserializer)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        value
+            .strip_suffix('s')
+            .and_then(|secs| secs.parse::<f64>().ok())
+            .filter(|secs| *secs >= 0.0)
+            .map(|secs| Some(Duration::from_secs_f64(secs)))
+            .ok_or_else(|| de::Error::custom(format!("invalid duration: {}", value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Recorded, Response, StandIn};
+    use crate::{ClientBuilder, RetryPolicy};
+    use serde_json::json;
+
+    async fn client(server: &StandIn) -> Client {
+        ClientBuilder::new()
+            .emulator(server.host())
+            .project("project")
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .await
+            .unwrap()
+    }
+
+    // Answers with the request body, named after the request path as the API does.
+    fn echo_server() -> StandIn {
+        StandIn::start(|request| {
+            let mut resource = request.json();
+            let name = request.path.trim_start_matches("/v1/");
+            resource["name"] = json!(name.split('?').next().unwrap());
+            Response::json(resource)
+        })
+    }
+
+    // Serves `pages` of `field`, the token of each page being its index.
+    fn paged_server(field: &'static str, pages: Vec<Vec<&'static str>>) -> StandIn {
+        StandIn::start(move |request| {
+            let index = request
+                .path
+                .split("pageToken=page%20")
+                .nth(1)
+                .map(|index| index.parse::<usize>().unwrap())
+                .unwrap_or(0);
+            let resources: Vec<_> = pages[index]
+                .iter()
+                .map(|name| json!({ "name": name }))
+                .collect();
+            let mut page = json!({ field: resources });
+            if index + 1 < pages.len() {
+                page["nextPageToken"] = json!(format!("page {}", index + 1));
+            }
+            Response::json(page)
+        })
+    }
+
+    fn paths(requests: Vec<Recorded>) -> Vec<String> {
+        requests.into_iter().map(|request| request.path).collect()
+    }
+
+    #[tokio::test]
+    async fn creates_topic_with_its_config() {
+        let server = echo_server();
+        let labels: HashMap<_, _> = vec![("team".to_string(), "ingest".to_string())]
+            .into_iter()
+            .collect();
+
+        let topic = client(&server)
+            .await
+            .create_topic(
+                "topic".to_string(),
+                TopicConfig {
+                    labels: Some(labels.clone()),
+                    message_retention_duration: Some(Duration::from_secs(604_800)),
+                },
+            )
+            .await
+            .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/v1/projects/project/topics/topic");
+        assert_eq!(
+            request.json(),
+            json!({
+                "name": "projects/project/topics/topic",
+                "labels": { "team": "ingest" },
+                "messageRetentionDuration": "604800s",
+            })
+        );
+        assert_eq!(topic.name, "projects/project/topics/topic");
+        assert_eq!(topic.config.labels, Some(labels));
+        assert_eq!(
+            topic.config.message_retention_duration,
+            Some(Duration::from_secs(604_800))
+        );
+    }
+
+    #[tokio::test]
+    async fn creates_subscription_with_its_flattened_config() {
+        let server = echo_server();
+
+        let subscription = client(&server)
+            .await
+            .create_subscription(
+                "subscription".to_string(),
+                "topic".to_string(),
+                SubscriptionConfig {
+                    ack_deadline_seconds: Some(20),
+                    filter: Some("attributes.source = \"github\"".to_string()),
+                    dead_letter_policy: Some(DeadLetterPolicy {
+                        dead_letter_topic: "projects/project/topics/dead".to_string(),
+                        max_delivery_attempts: Some(5),
+                    }),
+                    retry_policy: Some(SubscriptionRetryPolicy {
+                        minimum_backoff: Some(Duration::from_secs(10)),
+                        maximum_backoff: Some(Duration::from_millis(600_500)),
+                    }),
+                    enable_message_ordering: Some(true),
+                    expiration_policy: Some(ExpirationPolicy {
+                        ttl: Some(Duration::from_secs(86_400)),
+                    }),
+                    ..SubscriptionConfig::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "PUT");
+        assert_eq!(
+            request.path,
+            "/v1/projects/project/subscriptions/subscription"
+        );
+        assert_eq!(
+            request.json(),
+            json!({
+                "topic": "projects/project/topics/topic",
+                "ackDeadlineSeconds": 20,
+                "filter": "attributes.source = \"github\"",
+                "deadLetterPolicy": {
+                    "deadLetterTopic": "projects/project/topics/dead",
+                    "maxDeliveryAttempts": 5,
+                },
+                "retryPolicy": {
+                    "minimumBackoff": "10s",
+                    "maximumBackoff": "600.500000000s",
+                },
+                "enableMessageOrdering": true,
+                "expirationPolicy": { "ttl": "86400s" },
+            })
+        );
+        assert_eq!(
+            subscription.name,
+            "projects/project/subscriptions/subscription"
+        );
+        assert_eq!(
+            subscription.topic.as_deref(),
+            Some("projects/project/topics/topic")
+        );
+        assert_eq!(subscription.config.ack_deadline_seconds, Some(20));
+        let retry_policy = subscription.config.retry_policy.unwrap();
+        assert_eq!(
+            retry_policy.maximum_backoff,
+            Some(Duration::from_millis(600_500))
+        );
+    }
+
+    #[tokio::test]
+    async fn update_mask_lists_the_set_fields() {
+        let server = echo_server();
+
+        client(&server)
+            .await
+            .update_subscription(
+                "subscription".to_string(),
+                SubscriptionConfig {
+                    ack_deadline_seconds: Some(30),
+                    retain_acked_messages: Some(false),
+                    message_retention_duration: Some(Duration::from_secs(3_600)),
+                    ..SubscriptionConfig::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "PATCH");
+        assert_eq!(
+            request.path,
+            "/v1/projects/project/subscriptions/subscription"
+        );
+        assert_eq!(
+            request.json(),
+            json!({
+                "subscription": {
+                    "ackDeadlineSeconds": 30,
+                    "retainAckedMessages": false,
+                    "messageRetentionDuration": "3600s",
+                },
+                "updateMask": "ackDeadlineSeconds,messageRetentionDuration,retainAckedMessages",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_topics_across_pages() {
+        let server = paged_server(
+            "topics",
+            vec![
+                vec!["projects/project/topics/a", "projects/project/topics/b"],
+                vec!["projects/project/topics/c"],
+            ],
+        );
+        let client = client(&server).await;
+
+        let mut names = Vec::new();
+        let mut page_token = None;
+        loop {
+            let page = client.list_topics(Some(2), page_token).await.unwrap();
+            names.extend(page.topics.into_iter().map(|topic| topic.name));
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(
+            names,
+            vec![
+                "projects/project/topics/a",
+                "projects/project/topics/b",
+                "projects/project/topics/c",
+            ]
+        );
+        assert_eq!(
+            paths(server.requests()),
+            vec![
+                "/v1/projects/project/topics?pageSize=2",
+                "/v1/projects/project/topics?pageSize=2&pageToken=page%201",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_subscriptions_across_pages() {
+        let server = paged_server(
+            "subscriptions",
+            vec![
+                vec!["projects/project/subscriptions/a"],
+                vec![],
+                vec!["projects/project/subscriptions/b"],
+            ],
+        );
+        let client = client(&server).await;
+
+        let mut names = Vec::new();
+        let mut page_token = None;
+        loop {
+            let page = client.list_subscriptions(None, page_token).await.unwrap();
+            names.extend(page.subscriptions.into_iter().map(|s| s.name));
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(
+            names,
+            vec![
+                "projects/project/subscriptions/a",
+                "projects/project/subscriptions/b",
+            ]
+        );
+        assert_eq!(
+            paths(server.requests()),
+            vec![
+                "/v1/projects/project/subscriptions",
+                "/v1/projects/project/subscriptions?pageToken=page%201",
+                "/v1/projects/project/subscriptions?pageToken=page%202",
+            ]
+        );
+    }
+
+    #[test]
+    fn durations_round_trip_as_strings() {
+        for (duration, string) in [
+            (Duration::from_secs(604_800), "604800s"),
+            (Duration::from_millis(3_500), "3.500000000s"),
+            (Duration::from_nanos(1), "0.000000001s"),
+        ] {
+            let config = TopicConfig {
+                message_retention_duration: Some(duration),
+                ..TopicConfig::default()
+            };
+            let value = serde_json::to_value(&config).unwrap();
+            assert_eq!(value, json!({ "messageRetentionDuration": string }));
+            let config: TopicConfig = serde_json::from_value(value).unwrap();
+            assert_eq!(config.message_retention_duration, Some(duration));
+        }
+
+        let config: TopicConfig =
+            serde_json::from_value(json!({ "messageRetentionDuration": "3.5s" })).unwrap();
+        assert_eq!(
+            config.message_retention_duration,
+            Some(Duration::from_millis(3_500))
+        );
+        for invalid in &["604800", "-1s", "soon"] {
+            let result = serde_json::from_value::<TopicConfig>(
+                json!({ "messageRetentionDuration": invalid }),
+            );
+            assert!(result.is_err(), "{} was accepted", invalid);
+        }
+    }
+}
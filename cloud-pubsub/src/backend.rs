@@ -0,0 +1,110 @@
+use crate::client::Client;
+use crate::error;
+use crate::message::EncodedMessage;
+use futures::future::{BoxFuture, FutureExt};
+
+/// Publishes messages to topics, named relative to the project of the backend.
+///
+/// Implemented by [`Client`], and by [`MemoryPubSub`](crate::memory::MemoryPubSub) so code
+/// written against the trait can run without Google credentials.
+pub trait MessagePublisher: Send + Sync {
+    /// Publishes `message` to `topic`, the returned future resolving to the message id.
+    ///
+    /// [`MemoryPubSub`](crate::memory::MemoryPubSub) queues the message before returning,
+    /// [`Client`] only sends it once the future is polled. A caller keeping messages in order
+    /// awaits each one before publishing the next.
+    fn publish(
+        &self,
+        topic: &str,
+        message: EncodedMessage,
+    ) -> BoxFuture<'static, Result<String, error::Error>>;
+}
+
+/// Pulls messages from subscriptions, named relative to the project of the backend.
+pub trait MessageSubscriber: Send + Sync {
+    fn pull(
+        &self,
+        subscription: &str,
+        max_messages: usize,
+    ) -> BoxFuture<'_, Result<Vec<PulledMessage>, error::Error>>;
+
+    fn acknowledge(
+        &self,
+        subscription: &str,
+        ack_ids: Vec<String>,
+    ) -> BoxFuture<'_, Result<(), error::Error>>;
+
+    /// Makes the messages available for redelivery right away.
+    fn nack(
+        &self,
+        subscription: &str,
+        ack_ids: Vec<String>,
+    ) -> BoxFuture<'_, Result<(), error::Error>>;
+}
+
+/// Message pulled through a [`MessageSubscriber`].
+#[derive(Clone)]
+pub struct PulledMessage {
+    pub ack_id: String,
+    pub message_id: String,
+    pub message: EncodedMessage,
+}
+
+impl MessagePublisher for Client {
+    /// Sends one request per message, see [`Publisher`](crate::Publisher) for batching.
+    fn publish(
+        &self,
+        topic: &str,
+        message: EncodedMessage,
+    ) -> BoxFuture<'static, Result<String, error::Error>> {
+        let topic = self.topic(topic.to_string());
+        async move {
+            let response = topic.publish_message(message).await?;
+            response.message_ids.into_iter().next().ok_or_else(|| {
+                error::Error::Publisher("Publish response has no message id".to_string())
+            })
+        }
+        .boxed()
+    }
+}
+
+impl MessageSubscriber for Client {
+    fn pull(
+        &self,
+        subscription: &str,
+        max_messages: usize,
+    ) -> BoxFuture<'_, Result<Vec<PulledMessage>, error::Error>> {
+        let subscription = self.subscribe(subscription.to_string());
+        async move {
+            let max_messages = max_messages.min(i32::MAX as usize) as i32;
+            let messages = subscription.receive_messages(max_messages).await?;
+            Ok(messages
+                .into_iter()
+                .map(|m| PulledMessage {
+                    ack_id: m.ack_id().to_string(),
+                    message_id: m.message_id().to_string(),
+                    message: m.message().clone(),
+                })
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn acknowledge(
+        &self,
+        subscription: &str,
+        ack_ids: Vec<String>,
+    ) -> BoxFuture<'_, Result<(), error::Error>> {
+        let subscription = self.subscribe(subscription.to_string());
+        async move { subscription.acknowledge(ack_ids).await }.boxed()
+    }
+
+    fn nack(
+        &self,
+        subscription: &str,
+        ack_ids: Vec<String>,
+    ) -> BoxFuture<'_, Result<(), error::Error>> {
+        let subscription = self.subscribe(subscription.to_string());
+        async move { subscription.nack(ack_ids).await }.boxed()
+    }
+}
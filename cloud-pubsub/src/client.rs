@@ -1,27 +1,37 @@
+use crate::admin::{SubscriptionConfig, TopicConfig};
+use crate::credentials::Credentials;
 use crate::error;
+use crate::retry::RetryPolicy;
 use crate::subscription::Subscription;
 use crate::topic::Topic;
-use goauth::auth::JwtClaims;
-use goauth::scopes::Scope;
+use bytes::Bytes;
 use hyper::client::HttpConnector;
+use hyper::header::HeaderValue;
 use hyper_tls::HttpsConnector;
-use smpl_jwt::Jwt;
-use std::fs;
-use std::str::FromStr;
+use std::env;
+use std::io;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use tokio::task;
 use tokio::time;
 
-type HyperClient = Arc<hyper::Client<HttpsConnector<HttpConnector>, hyper::Body>>;
+pub(crate) type HyperClient = Arc<hyper::Client<HttpsConnector<HttpConnector>, hyper::Body>>;
+
+const DEFAULT_ENDPOINT: &str = "https://pubsub.googleapis.com";
 
 pub struct State {
     token: Option<goauth::auth::Token>,
-    credentials_string: String,
+    /// `None` when talking to the emulator, which takes no token.
+    credentials: Option<Credentials>,
     project: Option<String>,
+    endpoint: String,
+    user_agent: Option<String>,
+    timeout: Option<Duration>,
+    quota_project: Option<String>,
     hyper_client: HyperClient,
     running: Arc<AtomicBool>,
+    retry_policy: RetryPolicy,
 }
 
 impl State {
@@ -34,7 +44,123 @@ impl State {
     }
 
     pub fn project(&self) -> &str {
-        &(self.project.as_ref().expect("Google Cloud Project has not been set. If it is not in your credential file, call set_project to set it manually."))
+        self.project.as_ref().expect("Google Cloud Project has not been set. If it is not in your credential file, call set_project to set it manually.")
+    }
+}
+
+/// Settings of a [`Client`], the environment only filling what is not set explicitly.
+///
+/// `PUBSUB_EMULATOR_HOST` turns on the emulator mode when no endpoint is given, and the
+/// Application Default Credentials are used when no credentials are given.
+#[derive(Default)]
+pub struct ClientBuilder {
+    credentials: Option<Credentials>,
+    endpoint: Option<String>,
+    emulator: bool,
+    project: Option<String>,
+    user_agent: Option<String>,
+    timeout: Option<Duration>,
+    quota_project: Option<String>,
+    retry_policy: Option<RetryPolicy>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Base URL of the API, e.g. `https://europe-west1-pubsub.googleapis.com`.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into().trim_end_matches('/').to_string());
+        self.emulator = false;
+        self
+    }
+
+    /// Regional endpoint, keeping the messages in `region`.
+    pub fn region(self, region: &str) -> Self {
+        self.endpoint(format!("https://{}-pubsub.googleapis.com", region))
+    }
+
+    /// Talks to the emulator at `host`, e.g. `localhost:8085`, without authenticating.
+    pub fn emulator(mut self, host: &str) -> Self {
+        self.endpoint = Some(format!("http://{}", host));
+        self.emulator = true;
+        self
+    }
+
+    pub fn project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Limit on each attempt of a request, retried as a dropped connection.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Project billed for the requests, sent as `x-goog-user-project`.
+    pub fn quota_project(mut self, project: impl Into<String>) -> Self {
+        self.quota_project = Some(project.into());
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    pub async fn build(self) -> Result<Client, error::Error> {
+        let (endpoint, emulator) = match self.endpoint {
+            Some(endpoint) => (endpoint, self.emulator),
+            None => match env::var("PUBSUB_EMULATOR_HOST") {
+                Ok(host) => (format!("http://{}", host), true),
+                Err(_) => (DEFAULT_ENDPOINT.to_string(), false),
+            },
+        };
+
+        let hyper_client = setup_hyper();
+        let credentials = match (emulator, self.credentials) {
+            (true, _) => None,
+            (false, Some(credentials)) => Some(credentials),
+            (false, None) => Some(Credentials::find_default().await?),
+        };
+        let project = match (self.project, &credentials) {
+            (Some(project), _) => Some(project),
+            (None, Some(credentials)) => credentials.resolve_project(&hyper_client).await?,
+            (None, None) => env::var("PUBSUB_PROJECT_ID")
+                .or_else(|_| env::var("GOOGLE_CLOUD_PROJECT"))
+                .ok(),
+        };
+        let quota_project = self
+            .quota_project
+            .or_else(|| env::var("GOOGLE_CLOUD_QUOTA_PROJECT").ok());
+
+        let mut client = Client(Arc::new(RwLock::new(State {
+            token: None,
+            credentials,
+            project,
+            endpoint,
+            user_agent: self.user_agent,
+            timeout: self.timeout,
+            quota_project,
+            hyper_client,
+            running: Arc::new(AtomicBool::new(true)),
+            retry_policy: self.retry_policy.unwrap_or_default(),
+        })));
+
+        client.refresh_token().await?;
+        Ok(client)
     }
 }
 
@@ -47,24 +173,30 @@ impl Clone for Client {
 }
 
 impl Client {
-    pub async fn from_string(credentials_string: String) -> Result<Self, error::Error> {
-        let mut client = Client(Arc::new(RwLock::new(State {
-            token: None,
-            credentials_string,
-            project: None,
-            hyper_client: setup_hyper(),
-            running: Arc::new(AtomicBool::new(true)),
-        })));
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
 
-        match client.refresh_token().await {
-            Ok(_) => Ok(client),
-            Err(e) => Err(e),
-        }
+    /// Authenticates with the content of a service account key or authorized user file.
+    pub async fn from_string(credentials_string: String) -> Result<Self, error::Error> {
+        Self::from_credentials(Credentials::from_json(&credentials_string)?).await
     }
 
+    /// Authenticates with a service account key or authorized user file.
     pub async fn new(credentials_path: String) -> Result<Self, error::Error> {
-        let credentials_string = fs::read_to_string(credentials_path).unwrap();
-        Self::from_string(credentials_string).await
+        Self::from_credentials(Credentials::from_file(credentials_path)?).await
+    }
+
+    /// Authenticates with the Application Default Credentials, see
+    /// [`Credentials::find_default`].
+    pub async fn from_default_credentials() -> Result<Self, error::Error> {
+        ClientBuilder::new().build().await
+    }
+
+    /// Authenticates with `credentials`, the project being taken from the credentials, from
+    /// `GOOGLE_CLOUD_PROJECT` or from the metadata server, in that order.
+    pub async fn from_credentials(credentials: Credentials) -> Result<Self, error::Error> {
+        ClientBuilder::new().credentials(credentials).build().await
     }
 
     pub fn subscribe(&self, name: String) -> Subscription {
@@ -72,6 +204,7 @@ impl Client {
             client: Some(self.clone()),
             name: format!("projects/{}/subscriptions/{}", self.project(), name),
             topic: None,
+            config: SubscriptionConfig::default(),
         }
     }
 
@@ -83,10 +216,21 @@ impl Client {
         self.0.read().unwrap().project().to_string()
     }
 
+    /// Sets how the requests made through this client, and its topics and subscriptions, are
+    /// retried.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.0.write().unwrap().retry_policy = retry_policy;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.0.read().unwrap().retry_policy.clone()
+    }
+
     pub fn topic(&self, name: String) -> Topic {
         Topic {
             client: Some(Client(self.0.clone())),
             name: format!("projects/{}/topics/{}", self.project(), name),
+            config: TopicConfig::default(),
         }
     }
 
@@ -122,31 +266,20 @@ impl Client {
     }
 
     pub async fn refresh_token(&mut self) -> Result<(), error::Error> {
-        match self.get_token().await {
-            Ok(token) => {
-                self.0.write().unwrap().token = Some(token);
-                Ok(())
-            }
-            Err(e) => Err(error::Error::from(e)),
+        let (credentials, hyper_client) = {
+            let state = self.0.read().unwrap();
+            (state.credentials.clone(), state.hyper_client.clone())
+        };
+        if let Some(credentials) = credentials {
+            let token = credentials.token(&hyper_client).await?;
+            self.0.write().unwrap().token = Some(token);
         }
+        Ok(())
     }
 
-    async fn get_token(&mut self) -> Result<goauth::auth::Token, goauth::GoErr> {
-        let credentials =
-            goauth::credentials::Credentials::from_str(&self.0.read().unwrap().credentials_string)
-                .unwrap();
-
-        self.set_project(credentials.project());
-
-        let claims = JwtClaims::new(
-            credentials.iss(),
-            &Scope::PubSub,
-            credentials.token_uri(),
-            None,
-            None,
-        );
-        let jwt = Jwt::new(claims, credentials.rsa_key().unwrap(), None);
-        goauth::get_token(&jwt, &credentials).await
+    /// Base URL of the API, without trailing slash.
+    pub fn endpoint(&self) -> String {
+        self.0.read().unwrap().endpoint.clone()
     }
 
     pub(crate) fn request<T: Into<hyper::Body>>(
@@ -159,29 +292,179 @@ impl Client {
     {
         let mut req = hyper::Request::new(hyper::Body::from(data));
         *req.method_mut() = method;
-        req.headers_mut().insert(
+        let headers = req.headers_mut();
+        headers.insert(
             hyper::header::CONTENT_TYPE,
-            hyper::header::HeaderValue::from_static("application/json"),
+            HeaderValue::from_static("application/json"),
         );
         let readable = self.0.read().unwrap();
-        req.headers_mut().insert(
-            hyper::header::AUTHORIZATION,
-            hyper::header::HeaderValue::from_str(&format!(
-                "{} {}",
-                readable.token_type(),
-                readable.access_token()
-            ))
-            .unwrap(),
-        );
+        if let Some(token) = &readable.token {
+            headers.insert(
+                hyper::header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("{} {}", token.token_type(), token.access_token()))
+                    .unwrap(),
+            );
+        }
+        if let Some(value) = header_value(&readable.user_agent) {
+            headers.insert(hyper::header::USER_AGENT, value);
+        }
+        if let Some(value) = header_value(&readable.quota_project) {
+            headers.insert("x-goog-user-project", value);
+        }
         req
     }
 
+    /// Sends the request for `path`, relative to the `v1` API of the endpoint, retrying it
+    /// according to the retry policy, and returns the body of the successful response.
+    pub(crate) async fn perform(
+        &self,
+        method: hyper::Method,
+        path: &str,
+        data: String,
+    ) -> Result<Bytes, error::Error> {
+        let uri: hyper::Uri = format!("{}/v1/{}", self.endpoint(), path)
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.retry_policy()
+            .retry(|| self.perform_once(method.clone(), uri.clone(), data.clone()))
+            .await
+    }
+
+    async fn perform_once(
+        &self,
+        method: hyper::Method,
+        uri: hyper::Uri,
+        data: String,
+    ) -> Result<Bytes, error::Error> {
+        let mut req = self.request(method, data);
+        *req.uri_mut() = uri;
+
+        let exchange = async {
+            let response = self.hyper_client().request(req).await?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response).await?;
+            Ok::<_, error::Error>((status, body))
+        };
+        let timeout = self.0.read().unwrap().timeout;
+        let (status, body) = match timeout {
+            Some(timeout) => time::timeout(timeout, exchange)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Request timed out"))??,
+            None => exchange.await?,
+        };
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(error::Error::from_response(status, &body))
+        }
+    }
+
     pub fn hyper_client(&self) -> HyperClient {
         self.0.read().unwrap().hyper_client.clone()
     }
 }
 
-fn setup_hyper() -> HyperClient {
+pub(crate) fn setup_hyper() -> HyperClient {
     let https = HttpsConnector::new();
     Arc::new(hyper::Client::builder().build::<_, hyper::Body>(https))
 }
+
+fn header_value(value: &Option<String>) -> Option<HeaderValue> {
+    value
+        .as_deref()
+        .and_then(|value| HeaderValue::from_str(value).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Response, StandIn};
+    use serde_json::json;
+
+    // Serves both the metadata server token and the topics of the API.
+    fn server() -> StandIn {
+        StandIn::start(|request| {
+            if request.path.starts_with("/computeMetadata/") {
+                return Response::json(json!({
+                    "access_token": "ya29.metadata",
+                    "expires_in": 3599,
+                    "token_type": "Bearer",
+                }));
+            }
+            Response::json(json!({ "name": "projects/project/topics/topic" }))
+        })
+    }
+
+    fn metadata_server(server: &StandIn) -> Credentials {
+        Credentials::MetadataServer {
+            host: server.host().to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn emulator_sends_no_token() {
+        let server = server();
+        let client = ClientBuilder::new()
+            .emulator(server.host())
+            .project("project")
+            .build()
+            .await
+            .unwrap();
+
+        client.get_topic("topic".to_string()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/v1/projects/project/topics/topic");
+        assert!(!requests[0].headers.contains_key("authorization"));
+    }
+
+    #[tokio::test]
+    async fn sends_token_and_configured_headers() {
+        let server = server();
+        let client = ClientBuilder::new()
+            .credentials(metadata_server(&server))
+            .endpoint(format!("http://{}/", server.host()))
+            .project("project")
+            .user_agent("event-ingestor/1.0")
+            .quota_project("billing")
+            .build()
+            .await
+            .unwrap();
+
+        client.get_topic("topic".to_string()).await.unwrap();
+
+        let requests = server.requests_to("/v1/projects/project/topics/topic");
+        assert_eq!(requests.len(), 1);
+        let headers = &requests[0].headers;
+        assert_eq!(headers["authorization"], "Bearer ya29.metadata");
+        assert_eq!(headers["user-agent"], "event-ingestor/1.0");
+        assert_eq!(headers["x-goog-user-project"], "billing");
+    }
+
+    #[tokio::test]
+    async fn region_selects_regional_endpoint() {
+        let server = server();
+        let client = ClientBuilder::new()
+            .credentials(metadata_server(&server))
+            .project("project")
+            .region("europe-west1")
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(
+            client.endpoint(),
+            "https://europe-west1-pubsub.googleapis.com"
+        );
+
+        // The last of the endpoint settings wins.
+        let client = ClientBuilder::new()
+            .region("europe-west1")
+            .emulator(server.host())
+            .project("project")
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(client.endpoint(), format!("http://{}", server.host()));
+    }
+}
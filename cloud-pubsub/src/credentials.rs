@@ -0,0 +1,403 @@
+use crate::admin::encode_query_value;
+use crate::client::HyperClient;
+use crate::error;
+use goauth::auth::{JwtClaims, Token};
+use goauth::scopes::Scope;
+use hyper::{Body, Method, Request};
+use serde_derive::Deserialize;
+use smpl_jwt::Jwt;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time;
+
+const TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const METADATA_HOST: &str = "metadata.google.internal";
+const METADATA_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const WELL_KNOWN_FILE: &str = "application_default_credentials.json";
+
+/// Where the client gets its access tokens from.
+#[derive(Clone, Debug)]
+pub enum Credentials {
+    /// Service account key, the JSON file downloaded from the console.
+    ServiceAccount(goauth::credentials::Credentials),
+    /// User credentials, as written by `gcloud auth application-default login`.
+    AuthorizedUser(AuthorizedUser),
+    /// Account attached to the GCE instance, Cloud Run service or GKE workload.
+    MetadataServer { host: String },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct AuthorizedUser {
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+    pub quota_project_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CredentialsType {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+impl Credentials {
+    /// Parses a service account key or an authorized user file.
+    pub fn from_json(json: &str) -> Result<Self, error::Error> {
+        let CredentialsType { kind } = serde_json::from_str(json)?;
+        match kind.as_str() {
+            "service_account" => Ok(Credentials::ServiceAccount(
+                goauth::credentials::Credentials::from_str(json)?,
+            )),
+            "authorized_user" => Ok(Credentials::AuthorizedUser(serde_json::from_str(json)?)),
+            kind => Err(error::Error::Credentials(format!(
+                "Unsupported credentials type: {}",
+                kind
+            ))),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, error::Error> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Metadata server at `GCE_METADATA_HOST`, or `metadata.google.internal` when unset.
+    pub fn metadata_server() -> Self {
+        Credentials::MetadataServer {
+            host: env::var("GCE_METADATA_HOST").unwrap_or_else(|_| METADATA_HOST.to_string()),
+        }
+    }
+
+    /// Looks for the Application Default Credentials, in order:
+    ///
+    /// 1. the file named by `GOOGLE_APPLICATION_CREDENTIALS`,
+    /// 2. the file written by `gcloud auth application-default login`,
+    /// 3. the metadata server, when it answers.
+    pub async fn find_default() -> Result<Self, error::Error> {
+        if let Ok(path) = env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+            log::debug!("Using credentials from {}", path);
+            return Self::from_file(path);
+        }
+
+        if let Some(path) = well_known_file().filter(|path| path.is_file()) {
+            log::debug!("Using credentials from {}", path.display());
+            return Self::from_file(path);
+        }
+
+        let metadata_server = Self::metadata_server();
+        if metadata_server.is_reachable().await {
+            log::debug!("Using credentials from the metadata server");
+            return Ok(metadata_server);
+        }
+
+        Err(error::Error::Credentials(
+            "Could not find default credentials, set GOOGLE_APPLICATION_CREDENTIALS".to_string(),
+        ))
+    }
+
+    /// Project named by the credentials themselves, if any.
+    pub fn project(&self) -> Option<String> {
+        match self {
+            Credentials::ServiceAccount(credentials) => {
+                Some(credentials.project()).filter(|project| !project.is_empty())
+            }
+            Credentials::AuthorizedUser(user) => user.quota_project_id.clone(),
+            Credentials::MetadataServer { .. } => None,
+        }
+    }
+
+    /// Project of the credentials, falling back to `GOOGLE_CLOUD_PROJECT` and then to the
+    /// project the metadata server runs in.
+    pub(crate) async fn resolve_project(
+        &self,
+        client: &HyperClient,
+    ) -> Result<Option<String>, error::Error> {
+        if let Some(project) = self.project() {
+            return Ok(Some(project));
+        }
+        if let Ok(project) = env::var("GOOGLE_CLOUD_PROJECT") {
+            return Ok(Some(project));
+        }
+        match self {
+            Credentials::MetadataServer { host } => {
+                let body = metadata_get(client, host, "project/project-id").await?;
+                Ok(Some(String::from_utf8_lossy(&body).trim().to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) async fn token(&self, client: &HyperClient) -> Result<Token, error::Error> {
+        match self {
+            Credentials::ServiceAccount(credentials) => {
+                let claims = JwtClaims::new(
+                    credentials.iss(),
+                    &Scope::PubSub,
+                    credentials.token_uri(),
+                    None,
+                    None,
+                );
+                let jwt = Jwt::new(claims, credentials.rsa_key()?, None);
+                Ok(goauth::get_token(&jwt, credentials).await?)
+            }
+            Credentials::AuthorizedUser(user) => {
+                let form = format!(
+                    "grant_type=refresh_token&client_id={}&client_secret={}&refresh_token={}",
+                    encode_query_value(&user.client_id),
+                    encode_query_value(&user.client_secret),
+                    encode_query_value(&user.refresh_token)
+                );
+                let req = Request::builder()
+                    .method(Method::POST)
+                    .uri(TOKEN_URI)
+                    .header(
+                        hyper::header::CONTENT_TYPE,
+                        "application/x-www-form-urlencoded",
+                    )
+                    .body(Body::from(form))
+                    .unwrap();
+                let body = send(client, req).await?;
+                Ok(serde_json::from_slice(&body)?)
+            }
+            Credentials::MetadataServer { host } => {
+                let body =
+                    metadata_get(client, host, "instance/service-accounts/default/token").await?;
+                Ok(serde_json::from_slice(&body)?)
+            }
+        }
+    }
+
+    async fn is_reachable(&self) -> bool {
+        let host = match self {
+            Credentials::MetadataServer { host } => host,
+            _ => return true,
+        };
+        let req = match Request::get(format!("http://{}", host))
+            .header("Metadata-Flavor", "Google")
+            .body(Body::empty())
+        {
+            Ok(req) => req,
+            Err(_) => return false,
+        };
+        let client = hyper::Client::new();
+        match time::timeout(METADATA_PROBE_TIMEOUT, client.request(req)).await {
+            Ok(Ok(response)) => response
+                .headers()
+                .get("Metadata-Flavor")
+                .is_some_and(|flavor| flavor == "Google"),
+            _ => false,
+        }
+    }
+}
+
+async fn metadata_get(
+    client: &HyperClient,
+    host: &str,
+    path: &str,
+) -> Result<bytes::Bytes, error::Error> {
+    let req = Request::get(format!("http://{}/computeMetadata/v1/{}", host, path))
+        .header("Metadata-Flavor", "Google")
+        .body(Body::empty())
+        .map_err(|e| error::Error::Credentials(e.to_string()))?;
+    send(client, req).await
+}
+
+async fn send(client: &HyperClient, req: Request<Body>) -> Result<bytes::Bytes, error::Error> {
+    let response = client.request(req).await?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response).await?;
+    if status.is_success() {
+        Ok(body)
+    } else {
+        Err(error::Error::from_response(status, &body))
+    }
+}
+
+fn well_known_file() -> Option<PathBuf> {
+    let dir = match env::var("CLOUDSDK_CONFIG") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) if cfg!(windows) => PathBuf::from(env::var("APPDATA").ok()?).join("gcloud"),
+        Err(_) => PathBuf::from(env::var("HOME").ok()?)
+            .join(".config")
+            .join("gcloud"),
+    };
+    Some(dir.join(WELL_KNOWN_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::setup_hyper;
+    use crate::testing::{Response, StandIn};
+    use serde_json::json;
+
+    // The tests below change the environment of the whole process.
+    static ENV: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    const VARIABLES: [&str; 4] = [
+        "GOOGLE_APPLICATION_CREDENTIALS",
+        "CLOUDSDK_CONFIG",
+        "GCE_METADATA_HOST",
+        "GOOGLE_CLOUD_PROJECT",
+    ];
+
+    // Directory removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                env::temp_dir().join(format!("cloud-pubsub-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn authorized_user(&self, file: &str, client_id: &str) -> PathBuf {
+            let path = self.0.join(file);
+            let json = json!({
+                "type": "authorized_user",
+                "client_id": client_id,
+                "client_secret": "secret",
+                "refresh_token": "refresh",
+            });
+            fs::write(&path, json.to_string()).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn clear_env() {
+        for variable in VARIABLES {
+            env::remove_var(variable);
+        }
+    }
+
+    fn metadata_server() -> StandIn {
+        StandIn::start(|request| {
+            if request.headers.get("metadata-flavor").map(String::as_str) != Some("Google") {
+                return Response::new(403, "Missing Metadata-Flavor header");
+            }
+            let response = match request.path.as_str() {
+                "/" => Response::new(200, ""),
+                "/computeMetadata/v1/project/project-id" => {
+                    Response::new(200, "metadata-project\n")
+                }
+                "/computeMetadata/v1/instance/service-accounts/default/token" => {
+                    Response::json(json!({
+                        "access_token": "ya29.metadata",
+                        "expires_in": 3599,
+                        "token_type": "Bearer",
+                    }))
+                }
+                _ => Response::new(404, ""),
+            };
+            response.header("Metadata-Flavor", "Google")
+        })
+    }
+
+    fn client_id(credentials: &Credentials) -> &str {
+        match credentials {
+            Credentials::AuthorizedUser(user) => &user.client_id,
+            _ => panic!("Unexpected credentials: {:?}", credentials),
+        }
+    }
+
+    #[tokio::test]
+    async fn probes_metadata_server_by_its_flavor() {
+        let server = metadata_server();
+        let credentials = Credentials::MetadataServer {
+            host: server.host().to_string(),
+        };
+        assert!(credentials.is_reachable().await);
+        assert_eq!(server.requests()[0].headers["metadata-flavor"], "Google");
+
+        // Anything else listening on the host is not a metadata server.
+        let impostor = StandIn::start(|_| Response::new(200, ""));
+        let credentials = Credentials::MetadataServer {
+            host: impostor.host().to_string(),
+        };
+        assert!(!credentials.is_reachable().await);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        assert!(!Credentials::MetadataServer { host }.is_reachable().await);
+    }
+
+    #[tokio::test]
+    async fn gets_token_from_metadata_server() {
+        let server = metadata_server();
+        let credentials = Credentials::MetadataServer {
+            host: server.host().to_string(),
+        };
+
+        let token = credentials.token(&setup_hyper()).await.unwrap();
+        assert_eq!(token.access_token(), "ya29.metadata");
+        assert_eq!(token.token_type(), "Bearer");
+        assert_eq!(token.expires_in(), 3599);
+    }
+
+    #[tokio::test]
+    async fn resolves_project_of_metadata_server() {
+        let _env = ENV.lock().await;
+        clear_env();
+        let server = metadata_server();
+        let credentials = Credentials::MetadataServer {
+            host: server.host().to_string(),
+        };
+        let client = setup_hyper();
+
+        let project = credentials.resolve_project(&client).await.unwrap();
+        assert_eq!(project.as_deref(), Some("metadata-project"));
+
+        env::set_var("GOOGLE_CLOUD_PROJECT", "env-project");
+        let project = credentials.resolve_project(&client).await.unwrap();
+        clear_env();
+        assert_eq!(project.as_deref(), Some("env-project"));
+        assert_eq!(server.requests_to("project/project-id").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn finds_default_credentials_in_order() {
+        let _env = ENV.lock().await;
+        clear_env();
+        let server = metadata_server();
+        let dir = TempDir::new("adc");
+        let explicit = dir.authorized_user("explicit.json", "explicit");
+        dir.authorized_user(WELL_KNOWN_FILE, "well-known");
+        env::set_var("GOOGLE_APPLICATION_CREDENTIALS", &explicit);
+        env::set_var("CLOUDSDK_CONFIG", &dir.0);
+        env::set_var("GCE_METADATA_HOST", server.host());
+
+        let credentials = Credentials::find_default().await.unwrap();
+        assert_eq!(client_id(&credentials), "explicit");
+
+        env::remove_var("GOOGLE_APPLICATION_CREDENTIALS");
+        let credentials = Credentials::find_default().await.unwrap();
+        assert_eq!(client_id(&credentials), "well-known");
+
+        fs::remove_file(dir.0.join(WELL_KNOWN_FILE)).unwrap();
+        let credentials = Credentials::find_default().await.unwrap();
+        match credentials {
+            Credentials::MetadataServer { host } => assert_eq!(host, server.host()),
+            credentials => panic!("Unexpected credentials: {:?}", credentials),
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        env::set_var(
+            "GCE_METADATA_HOST",
+            listener.local_addr().unwrap().to_string(),
+        );
+        drop(listener);
+        let result = Credentials::find_default().await;
+        clear_env();
+        assert!(matches!(result, Err(error::Error::Credentials(_))));
+    }
+}
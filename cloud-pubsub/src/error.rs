@@ -1,3 +1,4 @@
+use hyper::StatusCode;
 use serde_derive::Deserialize;
 use std::fmt;
 use std::io;
@@ -15,6 +16,10 @@ pub enum Error {
     Base64(base64::DecodeError),
     #[serde(skip_deserializing)]
     IO(io::Error),
+    #[serde(skip_deserializing)]
+    Publisher(String),
+    #[serde(skip_deserializing)]
+    Credentials(String),
     PubSub {
         code: i32,
         message: String,
@@ -30,6 +35,8 @@ impl fmt::Display for Error {
             Error::Json(e) => write!(f, "Json({})", e),
             Error::Base64(e) => write!(f, "Base64({})", e),
             Error::IO(e) => write!(f, "IO({})", e),
+            Error::Publisher(e) => write!(f, "Publisher({})", e),
+            Error::Credentials(e) => write!(f, "Credentials({})", e),
             Error::PubSub {
                 code,
                 message,
@@ -45,6 +52,64 @@ impl fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: Error,
+}
+
+impl Error {
+    /// Builds the error of a non successful response, from the `{"error": {...}}` body
+    /// Google APIs answer with when there is one.
+    pub(crate) fn from_response(status: StatusCode, body: &[u8]) -> Error {
+        match serde_json::from_slice::<ErrorResponse>(body) {
+            Ok(ErrorResponse {
+                error: e @ Error::PubSub { .. },
+            }) => e,
+            _ => Error::PubSub {
+                code: status.as_u16() as i32,
+                status: status.canonical_reason().unwrap_or_default().to_string(),
+                message: String::from_utf8_lossy(body).into_owned(),
+            },
+        }
+    }
+
+    /// Whether the request may succeed if sent again: throttling, server errors, timeouts and
+    /// dropped connections.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::PubSub { code, status, .. } => {
+                matches!(code, 429 | 500 | 502 | 503 | 504)
+                    || matches!(status.as_str(), "UNAVAILABLE" | "DEADLINE_EXCEEDED")
+            }
+            Error::Http(e) => {
+                e.is_connect()
+                    || e.is_closed()
+                    || e.is_incomplete_message()
+                    || e.is_timeout()
+                    || has_io_source(e)
+            }
+            Error::IO(e) => is_retryable_io(e),
+            _ => false,
+        }
+    }
+
+    // Copy handed to every message of a failed batch.
+    pub(crate) fn share(&self) -> Error {
+        match self {
+            Error::PubSub {
+                code,
+                message,
+                status,
+            } => Error::PubSub {
+                code: *code,
+                message: message.clone(),
+                status: status.clone(),
+            },
+            e => Error::Publisher(e.to_string()),
+        }
+    }
+}
+
 impl From<goauth::GoErr> for Error {
     fn from(err: goauth::GoErr) -> Error {
         Error::PubSubAuth(err)
@@ -74,3 +139,101 @@ impl From<io::Error> for Error {
         Error::IO(err)
     }
 }
+
+fn has_io_source(e: &hyper::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            return is_retryable_io(e);
+        }
+        source = e.source();
+    }
+    false
+}
+
+fn is_retryable_io(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pubsub(code: i32, status: &str) -> Error {
+        Error::PubSub {
+            code,
+            message: String::new(),
+            status: status.to_string(),
+        }
+    }
+
+    #[test]
+    fn retries_throttling_and_server_errors() {
+        for code in [429, 500, 502, 503, 504] {
+            assert!(pubsub(code, "").is_retryable(), "{}", code);
+        }
+        assert!(pubsub(400, "UNAVAILABLE").is_retryable());
+        assert!(pubsub(400, "DEADLINE_EXCEEDED").is_retryable());
+
+        assert!(!pubsub(400, "INVALID_ARGUMENT").is_retryable());
+        assert!(!pubsub(404, "NOT_FOUND").is_retryable());
+    }
+
+    #[test]
+    fn retries_dropped_connections_and_timeouts() {
+        for kind in [io::ErrorKind::ConnectionReset, io::ErrorKind::TimedOut] {
+            assert!(Error::IO(io::Error::new(kind, "io")).is_retryable());
+        }
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "io");
+        assert!(!Error::IO(denied).is_retryable());
+        assert!(!Error::Publisher("closed".to_string()).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn retries_refused_connections() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let e = hyper::Client::new()
+            .get(uri.parse().unwrap())
+            .await
+            .unwrap_err();
+        assert!(Error::from(e).is_retryable());
+    }
+
+    #[test]
+    fn parses_google_error_bodies() {
+        let body = br#"{"error": {"code": 503, "message": "busy", "status": "UNAVAILABLE"}}"#;
+        match Error::from_response(StatusCode::SERVICE_UNAVAILABLE, body) {
+            Error::PubSub {
+                code,
+                message,
+                status,
+            } => assert_eq!(
+                (code, message.as_str(), status.as_str()),
+                (503, "busy", "UNAVAILABLE")
+            ),
+            e => panic!("Unexpected error: {}", e),
+        }
+
+        match Error::from_response(StatusCode::BAD_GATEWAY, b"upstream") {
+            Error::PubSub {
+                code,
+                message,
+                status,
+            } => assert_eq!(
+                (code, message.as_str(), status.as_str()),
+                (502, "upstream", "Bad Gateway")
+            ),
+            e => panic!("Unexpected error: {}", e),
+        }
+    }
+}
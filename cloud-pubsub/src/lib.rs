@@ -1,10 +1,32 @@
+pub mod admin;
+pub mod backend;
 pub mod client;
+pub mod credentials;
 pub mod error;
+pub mod memory;
 pub mod message;
+pub mod publisher;
+#[cfg(feature = "push")]
+pub mod push;
+pub mod retry;
+pub mod subscriber;
 pub mod subscription;
+#[cfg(test)]
+mod testing;
 pub mod topic;
 
-pub use client::Client;
-pub use message::{EncodedMessage, FromPubSubMessage};
+pub use admin::{
+    DeadLetterPolicy, ExpirationPolicy, SubscriptionConfig, SubscriptionRetryPolicy, TopicConfig,
+};
+pub use backend::{MessagePublisher, MessageSubscriber, PulledMessage};
+pub use client::{Client, ClientBuilder};
+pub use credentials::Credentials;
+pub use memory::{MemoryPubSub, PublishedMessage};
+pub use message::{EncodedMessage, FromPubSubMessage, ReceivedMessage};
+pub use publisher::{Publisher, PublisherConfig};
+pub use retry::RetryPolicy;
+pub use subscriber::{Lease, Leased, Subscriber, SubscriberConfig};
 pub use subscription::Subscription;
+pub use time::OffsetDateTime;
+pub use tokio_util::sync::CancellationToken;
 pub use topic::Topic;
@@ -0,0 +1,179 @@
+use crate::backend::{MessagePublisher, MessageSubscriber, PulledMessage};
+use crate::error;
+use crate::message::EncodedMessage;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Message recorded by a [`MemoryPubSub`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublishedMessage {
+    pub topic: String,
+    pub message_id: String,
+    pub data: Vec<u8>,
+    pub attributes: HashMap<String, String>,
+    pub ordering_key: Option<String>,
+}
+
+/// In-memory Pub/Sub, for tests of code written against [`MessagePublisher`] and
+/// [`MessageSubscriber`].
+///
+/// Every published message is recorded, and delivered to the subscriptions created on its
+/// topic before it was published. Pulled messages stay outstanding until they are acked or
+/// nacked, nacked ones being delivered again first. There are no ack deadlines. Clones share
+/// the same topics and subscriptions.
+#[derive(Clone, Default)]
+pub struct MemoryPubSub(Arc<Mutex<State>>);
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    published: Vec<PublishedMessage>,
+    subscriptions: HashMap<String, MemorySubscription>,
+}
+
+struct MemorySubscription {
+    topic: String,
+    pending: VecDeque<PulledMessage>,
+    outstanding: HashMap<String, PulledMessage>,
+}
+
+impl MemoryPubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates `subscription` on `topic`, receiving the messages published from now on.
+    pub fn create_subscription(&self, subscription: &str, topic: &str) {
+        self.0.lock().unwrap().subscriptions.insert(
+            subscription.to_string(),
+            MemorySubscription {
+                topic: topic.to_string(),
+                pending: VecDeque::new(),
+                outstanding: HashMap::new(),
+            },
+        );
+    }
+
+    /// Every message published so far, in order.
+    pub fn published(&self) -> Vec<PublishedMessage> {
+        self.0.lock().unwrap().published.clone()
+    }
+
+    pub fn published_to(&self, topic: &str) -> Vec<PublishedMessage> {
+        self.published()
+            .into_iter()
+            .filter(|m| m.topic == topic)
+            .collect()
+    }
+
+    /// Number of messages of `subscription` pulled but neither acked nor nacked.
+    pub fn outstanding(&self, subscription: &str) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .subscriptions
+            .get(subscription)
+            .map_or(0, |s| s.outstanding.len())
+    }
+
+    fn with_subscription<T>(
+        &self,
+        subscription: &str,
+        f: impl FnOnce(&mut MemorySubscription) -> T,
+    ) -> Result<T, error::Error> {
+        match self.0.lock().unwrap().subscriptions.get_mut(subscription) {
+            Some(state) => Ok(f(state)),
+            None => Err(error::Error::PubSub {
+                code: 404,
+                status: "Subscription Not Found".to_string(),
+                message: subscription.to_string(),
+            }),
+        }
+    }
+}
+
+impl MessagePublisher for MemoryPubSub {
+    fn publish(
+        &self,
+        topic: &str,
+        message: EncodedMessage,
+    ) -> BoxFuture<'static, Result<String, error::Error>> {
+        let data = match message.decode() {
+            Ok(data) => data,
+            Err(e) => return future::ready(Err(e.into())).boxed(),
+        };
+
+        let mut state = self.0.lock().unwrap();
+        state.next_id += 1;
+        let message_id = state.next_id.to_string();
+
+        for (name, subscription) in state.subscriptions.iter_mut() {
+            if subscription.topic == topic {
+                subscription.pending.push_back(PulledMessage {
+                    ack_id: format!("{}-{}", name, message_id),
+                    message_id: message_id.clone(),
+                    message: message.clone(),
+                });
+            }
+        }
+        state.published.push(PublishedMessage {
+            topic: topic.to_string(),
+            message_id: message_id.clone(),
+            data,
+            attributes: message.attributes().cloned().unwrap_or_default(),
+            ordering_key: message.ordering_key().map(str::to_string),
+        });
+
+        future::ready(Ok(message_id)).boxed()
+    }
+}
+
+impl MessageSubscriber for MemoryPubSub {
+    fn pull(
+        &self,
+        subscription: &str,
+        max_messages: usize,
+    ) -> BoxFuture<'_, Result<Vec<PulledMessage>, error::Error>> {
+        let pulled = self.with_subscription(subscription, |state| {
+            let count = max_messages.min(state.pending.len());
+            let pulled: Vec<_> = state.pending.drain(..count).collect();
+            for message in &pulled {
+                state
+                    .outstanding
+                    .insert(message.ack_id.clone(), message.clone());
+            }
+            pulled
+        });
+        future::ready(pulled).boxed()
+    }
+
+    fn acknowledge(
+        &self,
+        subscription: &str,
+        ack_ids: Vec<String>,
+    ) -> BoxFuture<'_, Result<(), error::Error>> {
+        let acked = self.with_subscription(subscription, |state| {
+            for id in &ack_ids {
+                state.outstanding.remove(id);
+            }
+        });
+        future::ready(acked).boxed()
+    }
+
+    fn nack(
+        &self,
+        subscription: &str,
+        ack_ids: Vec<String>,
+    ) -> BoxFuture<'_, Result<(), error::Error>> {
+        let nacked = self.with_subscription(subscription, |state| {
+            // Pushed to the front in reverse so they keep their order.
+            for id in ack_ids.iter().rev() {
+                if let Some(message) = state.outstanding.remove(id) {
+                    state.pending.push_front(message);
+                }
+            }
+        });
+        future::ready(nacked).boxed()
+    }
+}
@@ -1,7 +1,9 @@
 use crate::error;
+use crate::subscription::Subscription;
 use base64::{self, Engine};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
+use time::OffsetDateTime;
 
 #[derive(Deserialize, Clone, Serialize)]
 pub struct EncodedMessage {
@@ -17,6 +19,12 @@ where
     Self: std::marker::Sized,
 {
     fn from(message: EncodedMessage) -> Result<Self, error::Error>;
+
+    /// Converts a pulled message, with access to its metadata. Defaults to
+    /// [`FromPubSubMessage::from`].
+    fn from_received(message: &ReceivedMessage) -> Result<Self, error::Error> {
+        Self::from(message.message().clone())
+    }
 }
 
 impl EncodedMessage {
@@ -28,6 +36,15 @@ impl EncodedMessage {
         self.attributes.as_ref()
     }
 
+    pub fn ordering_key(&self) -> Option<&str> {
+        self.ordering_key.as_deref()
+    }
+
+    // Size counted against the flow control of a subscriber.
+    pub(crate) fn size(&self) -> usize {
+        self.data.len()
+    }
+
     pub fn new<T: serde::Serialize>(
         data: &T,
         attributes: Option<HashMap<String, String>>,
@@ -42,14 +59,158 @@ impl EncodedMessage {
         attributes: Option<HashMap<String, String>>,
         ordering_key: Option<String>
     ) -> Self {
-        let data = base64::engine::general_purpose::STANDARD.encode(&incoming);
+        let data = base64::engine::general_purpose::STANDARD.encode(incoming);
         EncodedMessage { data, attributes, ordering_key}
     }
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct Message {
-    #[serde(alias = "ackId")]
     pub(crate) ack_id: String,
-    pub(crate) message: EncodedMessage,
+    pub(crate) message: PubsubMessage,
+    pub(crate) delivery_attempt: Option<i32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PubsubMessage {
+    #[serde(flatten)]
+    pub(crate) encoded: EncodedMessage,
+    #[serde(default)]
+    pub(crate) message_id: String,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub(crate) publish_time: Option<OffsetDateTime>,
+}
+
+/// Message pulled from a subscription, kept until it is acked or nacked.
+#[derive(Clone)]
+pub struct ReceivedMessage {
+    ack_id: String,
+    message: EncodedMessage,
+    message_id: String,
+    publish_time: Option<OffsetDateTime>,
+    delivery_attempt: Option<i32>,
+    subscription: Subscription,
+}
+
+impl ReceivedMessage {
+    pub(crate) fn new(subscription: Subscription, message: Message) -> Self {
+        ReceivedMessage {
+            ack_id: message.ack_id,
+            message: message.message.encoded,
+            message_id: message.message.message_id,
+            publish_time: message.message.publish_time,
+            delivery_attempt: message.delivery_attempt,
+            subscription,
+        }
+    }
+
+    pub fn ack_id(&self) -> &str {
+        &self.ack_id
+    }
+
+    pub fn message(&self) -> &EncodedMessage {
+        &self.message
+    }
+
+    /// Id given by the server when the message was published, the same on every delivery.
+    pub fn message_id(&self) -> &str {
+        &self.message_id
+    }
+
+    pub fn publish_time(&self) -> Option<OffsetDateTime> {
+        self.publish_time
+    }
+
+    /// Number of times the message was delivered, only counted by subscriptions with a
+    /// dead letter policy.
+    pub fn delivery_attempt(&self) -> Option<i32> {
+        self.delivery_attempt
+    }
+
+    pub fn ordering_key(&self) -> Option<&str> {
+        self.message.ordering_key()
+    }
+
+    pub fn attributes(&self) -> Option<&HashMap<String, String>> {
+        self.message.attributes()
+    }
+
+    pub fn decode<T: FromPubSubMessage>(&self) -> Result<T, error::Error> {
+        T::from_received(self)
+    }
+
+    pub async fn ack(self) -> Result<(), error::Error> {
+        self.subscription.acknowledge(vec![self.ack_id]).await
+    }
+
+    /// Makes the message available for redelivery right away.
+    pub async fn nack(self) -> Result<(), error::Error> {
+        self.subscription.nack(vec![self.ack_id]).await
+    }
+
+    /// Pushes the ack deadline to `seconds` from now, to keep processing the message.
+    pub async fn extend(&self, seconds: i32) -> Result<(), error::Error> {
+        self.subscription
+            .modify_ack_deadline(vec![self.ack_id.clone()], seconds)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use time::format_description::well_known::Rfc3339;
+
+    fn received(entry: serde_json::Value) -> ReceivedMessage {
+        let subscription = Subscription {
+            name: "projects/project/subscriptions/subscription".to_string(),
+            topic: None,
+            config: Default::default(),
+            client: None,
+        };
+        ReceivedMessage::new(subscription, serde_json::from_value(entry).unwrap())
+    }
+
+    #[test]
+    fn reads_a_received_messages_entry() {
+        let message = received(json!({
+            "ackId": "ack-1",
+            "message": {
+                "data": "aGVsbG8=",
+                "attributes": { "source": "github" },
+                "messageId": "42",
+                "publishTime": "2024-05-01T12:30:00.123Z",
+                "orderingKey": "repo-1",
+            },
+            "deliveryAttempt": 3,
+        }));
+
+        assert_eq!(message.ack_id(), "ack-1");
+        assert_eq!(message.message_id(), "42");
+        assert_eq!(
+            message.publish_time(),
+            Some(OffsetDateTime::parse("2024-05-01T12:30:00.123Z", &Rfc3339).unwrap())
+        );
+        assert_eq!(message.delivery_attempt(), Some(3));
+        assert_eq!(message.ordering_key(), Some("repo-1"));
+        assert_eq!(message.attributes().unwrap()["source"], "github");
+        assert_eq!(message.message().decode().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn optional_fields_may_be_missing() {
+        let message = received(json!({
+            "ackId": "ack-1",
+            "message": { "data": "aGVsbG8=", "messageId": "42" },
+        }));
+
+        assert_eq!(message.message_id(), "42");
+        assert_eq!(message.publish_time(), None);
+        assert_eq!(message.delivery_attempt(), None);
+        assert_eq!(message.ordering_key(), None);
+        assert!(message.attributes().is_none());
+    }
 }
@@ -0,0 +1,365 @@
+use crate::error;
+use crate::topic::Topic;
+use crate::EncodedMessage;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Instant};
+
+/// Pub/Sub rejects publish requests larger than 10 MB.
+pub const MAX_REQUEST_BYTES: usize = 10_000_000;
+/// Pub/Sub rejects publish requests with more than 1000 messages.
+pub const MAX_REQUEST_MESSAGES: usize = 1000;
+
+// Room left for `{"messages":[]}` around the serialized messages.
+const REQUEST_OVERHEAD_BYTES: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct PublisherConfig {
+    /// Flush once this many messages are buffered.
+    pub max_messages: usize,
+    /// Flush once the buffered messages reach this size, capped to what a single
+    /// request can carry.
+    pub max_bytes: usize,
+    /// Flush once the oldest buffered message waited this long.
+    pub max_delay: Duration,
+}
+
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        PublisherConfig {
+            max_messages: 100,
+            max_bytes: 1_000_000,
+            max_delay: Duration::from_millis(10),
+        }
+    }
+}
+
+impl PublisherConfig {
+    fn max_messages(&self) -> usize {
+        self.max_messages.clamp(1, MAX_REQUEST_MESSAGES)
+    }
+
+    fn max_bytes(&self) -> usize {
+        self.max_bytes
+            .min(MAX_REQUEST_BYTES - REQUEST_OVERHEAD_BYTES)
+    }
+}
+
+struct Pending {
+    message: EncodedMessage,
+    size: usize,
+    result: oneshot::Sender<Result<String, error::Error>>,
+}
+
+/// Buffers messages published to a topic and sends them in batches.
+///
+/// Batches are sent one after the other, in the order the messages were published. The
+/// background task flushes what is left and stops once every clone of the publisher is
+/// dropped. Must be created from within a Tokio runtime.
+#[derive(Clone)]
+pub struct Publisher {
+    sender: mpsc::UnboundedSender<Pending>,
+    max_bytes: usize,
+}
+
+impl Publisher {
+    pub(crate) fn new(topic: Topic, config: PublisherConfig) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let max_bytes = config.max_bytes();
+        tokio::spawn(run(topic, config, receiver));
+        Publisher { sender, max_bytes }
+    }
+
+    /// Queues `message` for the next batch.
+    ///
+    /// The message is queued right away, the returned future resolves to its message id
+    /// once its batch is sent.
+    pub fn publish(
+        &self,
+        message: EncodedMessage,
+    ) -> impl Future<Output = Result<String, error::Error>> {
+        let (result, receiver) = oneshot::channel();
+        let queued = self.queue(message, result);
+
+        async move {
+            queued?;
+            receiver.await.unwrap_or_else(|_| {
+                Err(error::Error::Publisher(
+                    "Publisher stopped before sending the message".to_string(),
+                ))
+            })
+        }
+    }
+
+    fn queue(
+        &self,
+        message: EncodedMessage,
+        result: oneshot::Sender<Result<String, error::Error>>,
+    ) -> Result<(), error::Error> {
+        // The comma separating it from the other messages is counted as well.
+        let size = serde_json::to_string(&message)?.len() + 1;
+        if size > self.max_bytes {
+            return Err(error::Error::Publisher(format!(
+                "Message of {} bytes exceeds the {} bytes limit",
+                size, self.max_bytes
+            )));
+        }
+
+        self.sender
+            .send(Pending {
+                message,
+                size,
+                result,
+            })
+            .map_err(|_| error::Error::Publisher("Publisher is closed".to_string()))
+    }
+}
+
+async fn run(
+    topic: Topic,
+    config: PublisherConfig,
+    mut receiver: mpsc::UnboundedReceiver<Pending>,
+) {
+    let max_messages = config.max_messages();
+    let max_bytes = config.max_bytes();
+    let mut carry: Option<Pending> = None;
+
+    loop {
+        let first = match carry.take() {
+            Some(pending) => pending,
+            None => match receiver.recv().await {
+                Some(pending) => pending,
+                None => return,
+            },
+        };
+
+        let deadline = Instant::now() + config.max_delay;
+        let mut bytes = first.size;
+        let mut batch = vec![first];
+
+        while batch.len() < max_messages {
+            match time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(pending)) if bytes + pending.size > max_bytes => {
+                    carry = Some(pending);
+                    break;
+                }
+                Ok(Some(pending)) => {
+                    bytes += pending.size;
+                    batch.push(pending);
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        flush(&topic, batch).await;
+    }
+}
+
+async fn flush(topic: &Topic, batch: Vec<Pending>) {
+    let (messages, results): (Vec<_>, Vec<_>) =
+        batch.into_iter().map(|p| (p.message, p.result)).unzip();
+    let count = messages.len();
+
+    match topic.publish_messages(messages).await {
+        Ok(response) if response.message_ids.len() == count => {
+            for (result, id) in results.into_iter().zip(response.message_ids) {
+                let _ = result.send(Ok(id));
+            }
+        }
+        Ok(response) => {
+            let e = format!(
+                "Expected {} message ids, received {}",
+                count,
+                response.message_ids.len()
+            );
+            for result in results {
+                let _ = result.send(Err(error::Error::Publisher(e.clone())));
+            }
+        }
+        Err(e) => {
+            log::error!("Failed publishing batch of {} messages: {}", count, e);
+            for result in results {
+                let _ = result.send(Err(e.share()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Response, StandIn};
+    use crate::{ClientBuilder, RetryPolicy};
+    use futures::future::join_all;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Answers every publish request with one sequential id per message.
+    fn publish_server() -> StandIn {
+        let next_id = AtomicUsize::new(0);
+        StandIn::start(move |request| {
+            let count = request.json()["messages"].as_array().unwrap().len();
+            let ids: Vec<String> = (0..count)
+                .map(|_| next_id.fetch_add(1, Ordering::SeqCst).to_string())
+                .collect();
+            Response::json(json!({ "messageIds": ids }))
+        })
+    }
+
+    async fn publisher(server: &StandIn, config: PublisherConfig) -> Publisher {
+        let client = ClientBuilder::new()
+            .emulator(server.host())
+            .project("project")
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .await
+            .unwrap();
+        client.topic("topic".to_string()).publisher(config)
+    }
+
+    fn message(data: &str) -> EncodedMessage {
+        EncodedMessage::new_binary(&data, None, None)
+    }
+
+    fn batch_sizes(server: &StandIn) -> Vec<usize> {
+        server
+            .requests_to("/v1/projects/project/topics/topic:publish")
+            .iter()
+            .map(|request| {
+                assert_eq!(request.method, "POST");
+                request.json()["messages"].as_array().unwrap().len()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn flushes_once_max_messages_are_buffered() {
+        let server = publish_server();
+        let publisher = publisher(
+            &server,
+            PublisherConfig {
+                max_messages: 2,
+                max_delay: Duration::from_secs(3600),
+                ..PublisherConfig::default()
+            },
+        )
+        .await;
+
+        let results = join_all((0..4).map(|i| publisher.publish(message(&i.to_string())))).await;
+        let ids: Vec<String> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(ids, ["0", "1", "2", "3"]);
+        assert_eq!(batch_sizes(&server), [2, 2]);
+    }
+
+    #[tokio::test]
+    async fn flushes_once_max_delay_passed() {
+        let server = publish_server();
+        let publisher = publisher(
+            &server,
+            PublisherConfig {
+                max_delay: Duration::from_millis(50),
+                ..PublisherConfig::default()
+            },
+        )
+        .await;
+
+        let start = Instant::now();
+        let id = publisher.publish(message("alone")).await.unwrap();
+        assert_eq!(id, "0");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(batch_sizes(&server), [1]);
+    }
+
+    #[tokio::test]
+    async fn carries_message_overflowing_max_bytes_into_next_batch() {
+        let server = publish_server();
+        let size = serde_json::to_string(&message("a")).unwrap().len() + 1;
+        let publisher = publisher(
+            &server,
+            PublisherConfig {
+                max_bytes: 2 * size,
+                max_delay: Duration::from_millis(50),
+                ..PublisherConfig::default()
+            },
+        )
+        .await;
+
+        let results = join_all(
+            ["a", "b", "c"]
+                .iter()
+                .map(|data| publisher.publish(message(data))),
+        )
+        .await;
+        let ids: Vec<String> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(ids, ["0", "1", "2"]);
+        assert_eq!(batch_sizes(&server), [2, 1]);
+    }
+
+    #[tokio::test]
+    async fn rejects_message_larger_than_max_bytes() {
+        let server = publish_server();
+        let publisher = publisher(
+            &server,
+            PublisherConfig {
+                max_bytes: 10,
+                ..PublisherConfig::default()
+            },
+        )
+        .await;
+
+        match publisher.publish(message("too large")).await {
+            Err(error::Error::Publisher(e)) => assert!(e.contains("exceeds the 10 bytes limit")),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fails_every_message_when_ids_are_missing() {
+        let server = StandIn::start(|_| Response::json(json!({ "messageIds": ["0"] })));
+        let publisher = publisher(&server, PublisherConfig::default()).await;
+
+        let results = join_all(vec![
+            publisher.publish(message("a")),
+            publisher.publish(message("b")),
+        ])
+        .await;
+        for result in results {
+            match result {
+                Err(error::Error::Publisher(e)) => {
+                    assert_eq!(e, "Expected 2 message ids, received 1")
+                }
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_every_message_of_a_rejected_batch() {
+        let server = StandIn::start(|_| {
+            Response::new(
+                403,
+                json!({"error": {"code": 403, "message": "denied", "status": "PERMISSION_DENIED"}})
+                    .to_string(),
+            )
+        });
+        let publisher = publisher(&server, PublisherConfig::default()).await;
+
+        let results = join_all(vec![
+            publisher.publish(message("a")),
+            publisher.publish(message("b")),
+        ])
+        .await;
+        for result in results {
+            match result {
+                Err(error::Error::PubSub { code, status, .. }) => {
+                    assert_eq!(code, 403);
+                    assert_eq!(status, "PERMISSION_DENIED");
+                }
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+    }
+}
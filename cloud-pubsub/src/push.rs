@@ -0,0 +1,625 @@
+//! Receiver of push subscriptions, as an actix-web resource.
+//!
+//! Requires the `push` feature.
+
+use crate::client::{setup_hyper, HyperClient};
+use crate::error;
+use crate::message::{EncodedMessage, PubsubMessage};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, Resource, ResponseError};
+use base64::Engine;
+use ring::signature::{RsaPublicKeyComponents, RSA_PKCS1_2048_8192_SHA256};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use time::OffsetDateTime;
+
+/// Keys Google signs the OIDC tokens of push requests with.
+pub const GOOGLE_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+
+const ISSUERS: [&str; 2] = ["accounts.google.com", "https://accounts.google.com"];
+const CLOCK_SKEW: u64 = 60;
+const MIN_JWKS_REFRESH: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PushEnvelope {
+    message: PubsubMessage,
+    subscription: String,
+    delivery_attempt: Option<i32>,
+}
+
+/// Message delivered by a push subscription.
+pub struct PushMessage {
+    subscription: String,
+    message: EncodedMessage,
+    message_id: String,
+    publish_time: Option<OffsetDateTime>,
+    delivery_attempt: Option<i32>,
+}
+
+impl PushMessage {
+    /// Full name of the subscription, `projects/{project}/subscriptions/{subscription}`.
+    pub fn subscription(&self) -> &str {
+        &self.subscription
+    }
+
+    pub fn message(&self) -> &EncodedMessage {
+        &self.message
+    }
+
+    pub fn message_id(&self) -> &str {
+        &self.message_id
+    }
+
+    pub fn publish_time(&self) -> Option<OffsetDateTime> {
+        self.publish_time
+    }
+
+    pub fn delivery_attempt(&self) -> Option<i32> {
+        self.delivery_attempt
+    }
+
+    pub fn ordering_key(&self) -> Option<&str> {
+        self.message.ordering_key()
+    }
+
+    pub fn attributes(&self) -> Option<&HashMap<String, String>> {
+        self.message.attributes()
+    }
+}
+
+#[derive(Debug)]
+pub enum PushError {
+    Unauthorized(String),
+    MalformedEnvelope(String),
+    Handler(String),
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PushError::Unauthorized(e) => write!(f, "Unauthorized({})", e),
+            PushError::MalformedEnvelope(e) => write!(f, "MalformedEnvelope({})", e),
+            PushError::Handler(e) => write!(f, "Handler({})", e),
+        }
+    }
+}
+
+impl std::error::Error for PushError {}
+
+// Any status outside of 2xx nacks the message.
+impl ResponseError for PushError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PushError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            PushError::MalformedEnvelope(_) => StatusCode::BAD_REQUEST,
+            PushError::Handler(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kid: String,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+/// RSA public keys of a JSON Web Key Set, by key id.
+#[derive(Clone, Default)]
+pub struct Jwks {
+    keys: HashMap<String, RsaPublicKeyComponents<Vec<u8>>>,
+}
+
+impl Jwks {
+    /// Parses a JWKS document, keys other than RSA ones are skipped.
+    pub fn from_json(json: &str) -> Result<Self, error::Error> {
+        let set: JwkSet = serde_json::from_str(json)?;
+        let mut keys = HashMap::new();
+        for jwk in set.keys {
+            if let (Some(n), Some(e), "RSA") = (jwk.n, jwk.e, jwk.kty.as_str()) {
+                let n = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(n)?;
+                let e = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(e)?;
+                keys.insert(jwk.kid, RsaPublicKeyComponents { n, e });
+            }
+        }
+        Ok(Jwks { keys })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, error::Error> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub async fn fetch(url: &str) -> Result<Self, error::Error> {
+        Self::fetch_with(&setup_hyper(), url).await
+    }
+
+    async fn fetch_with(client: &HyperClient, url: &str) -> Result<Self, error::Error> {
+        let uri: hyper::Uri = url
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let response = client.get(uri).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response).await?;
+        if !status.is_success() {
+            return Err(error::Error::from_response(status, &body));
+        }
+        Self::from_json(&String::from_utf8_lossy(&body))
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    aud: String,
+    iss: String,
+    exp: u64,
+    iat: u64,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+/// Checks the OIDC token Pub/Sub attaches to push requests.
+///
+/// The token must be signed by a key of the JWKS, issued by Google, for `audience` and on
+/// behalf of `service_account_email`. When built with [`PushVerifier::google`] an unknown key
+/// id reloads the keys, at most once a minute.
+pub struct PushVerifier {
+    audience: String,
+    service_account_email: String,
+    jwks: RwLock<Jwks>,
+    jwks_url: Option<String>,
+    refreshed_at: RwLock<Option<Instant>>,
+    refresh: tokio::sync::Mutex<()>,
+    hyper_client: HyperClient,
+}
+
+impl PushVerifier {
+    /// Verifier with fixed keys, e.g. loaded with [`Jwks::from_file`].
+    pub fn new(audience: String, service_account_email: String, jwks: Jwks) -> Self {
+        PushVerifier {
+            audience,
+            service_account_email,
+            jwks: RwLock::new(jwks),
+            jwks_url: None,
+            refreshed_at: RwLock::new(None),
+            refresh: tokio::sync::Mutex::new(()),
+            hyper_client: setup_hyper(),
+        }
+    }
+
+    /// Verifier with the keys published at `jwks_url`, loaded on first use.
+    pub fn from_url(audience: String, service_account_email: String, jwks_url: String) -> Self {
+        PushVerifier {
+            jwks_url: Some(jwks_url),
+            ..Self::new(audience, service_account_email, Jwks::default())
+        }
+    }
+
+    /// Verifier with the keys of Google, see [`GOOGLE_JWKS_URL`].
+    pub fn google(audience: String, service_account_email: String) -> Self {
+        Self::from_url(audience, service_account_email, GOOGLE_JWKS_URL.to_string())
+    }
+
+    /// Verifies the `Authorization: Bearer` token of a push request.
+    pub async fn verify(&self, req: &HttpRequest) -> Result<(), PushError> {
+        let token = req
+            .headers()
+            .get(actix_web::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| PushError::Unauthorized("missing bearer token".to_string()))?;
+        self.verify_token(token).await
+    }
+
+    pub async fn verify_token(&self, token: &str) -> Result<(), PushError> {
+        let unauthorized = |e: &str| PushError::Unauthorized(e.to_string());
+
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature)) if parts.next().is_none() => {
+                (header, payload, signature)
+            }
+            _ => return Err(unauthorized("malformed token")),
+        };
+        let header: Header =
+            decode_json(header).map_err(|_| unauthorized("malformed token header"))?;
+        if header.alg != "RS256" {
+            return Err(unauthorized("unsupported token algorithm"));
+        }
+        let kid = header.kid.unwrap_or_default();
+        let key = self
+            .key(&kid)
+            .await
+            .ok_or_else(|| unauthorized("unknown signing key"))?;
+
+        let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| unauthorized("malformed token signature"))?;
+        let signed = &token[..header_payload_len(token)];
+        key.verify(&RSA_PKCS1_2048_8192_SHA256, signed.as_bytes(), &signature)
+            .map_err(|_| unauthorized("invalid token signature"))?;
+
+        let claims: Claims =
+            decode_json(payload).map_err(|_| unauthorized("malformed token claims"))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if !ISSUERS.contains(&claims.iss.as_str()) {
+            return Err(unauthorized("unexpected token issuer"));
+        }
+        if claims.aud != self.audience {
+            return Err(unauthorized("unexpected token audience"));
+        }
+        if claims.exp + CLOCK_SKEW < now || claims.iat > now + CLOCK_SKEW {
+            return Err(unauthorized("token expired"));
+        }
+        if !claims.email_verified
+            || claims.email.as_deref() != Some(self.service_account_email.as_str())
+        {
+            return Err(unauthorized("unexpected token service account"));
+        }
+        Ok(())
+    }
+
+    async fn key(&self, kid: &str) -> Option<RsaPublicKeyComponents<Vec<u8>>> {
+        if let Some(key) = self.jwks.read().unwrap().keys.get(kid) {
+            return Some(key.clone());
+        }
+
+        let url = self.jwks_url.as_ref()?;
+        let _guard = self.refresh.lock().await;
+        // Reloaded by a concurrent request while waiting for the lock.
+        if let Some(key) = self.jwks.read().unwrap().keys.get(kid) {
+            return Some(key.clone());
+        }
+        let recently = self
+            .refreshed_at
+            .read()
+            .unwrap()
+            .is_some_and(|at| at.elapsed() < MIN_JWKS_REFRESH);
+        if recently {
+            return None;
+        }
+
+        *self.refreshed_at.write().unwrap() = Some(Instant::now());
+        match Jwks::fetch_with(&self.hyper_client, url).await {
+            Ok(jwks) => *self.jwks.write().unwrap() = jwks,
+            Err(e) => log::error!("Failed loading JWKS from {}: {}", url, e),
+        }
+        self.jwks.read().unwrap().keys.get(kid).cloned()
+    }
+}
+
+/// Resource receiving the push requests at `path`.
+///
+/// Requests without a valid token are rejected before reaching `handler`. The message is
+/// acked when `handler` succeeds and nacked otherwise.
+pub fn resource<H, F, E>(path: &str, verifier: PushVerifier, handler: H) -> Resource
+where
+    H: Fn(PushMessage) -> F + Clone + 'static,
+    F: Future<Output = Result<(), E>> + 'static,
+    E: fmt::Display,
+{
+    let verifier = Arc::new(verifier);
+    web::resource(path).route(web::post().to(move |req: HttpRequest, body: web::Bytes| {
+        let verifier = Arc::clone(&verifier);
+        let handler = handler.clone();
+        async move {
+            let result = receive(&verifier, handler, &req, &body).await;
+            if let Err(e) = &result {
+                log::warn!("Push request rejected: {}", e);
+            }
+            result
+        }
+    }))
+}
+
+async fn receive<H, F, E>(
+    verifier: &PushVerifier,
+    handler: H,
+    req: &HttpRequest,
+    body: &[u8],
+) -> Result<HttpResponse, PushError>
+where
+    H: Fn(PushMessage) -> F,
+    F: Future<Output = Result<(), E>>,
+    E: fmt::Display,
+{
+    verifier.verify(req).await?;
+
+    let envelope: PushEnvelope =
+        serde_json::from_slice(body).map_err(|e| PushError::MalformedEnvelope(e.to_string()))?;
+    let message = PushMessage {
+        subscription: envelope.subscription,
+        message: envelope.message.encoded,
+        message_id: envelope.message.message_id,
+        publish_time: envelope.message.publish_time,
+        delivery_attempt: envelope.delivery_attempt,
+    };
+
+    handler(message)
+        .await
+        .map_err(|e| PushError::Handler(e.to_string()))?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(part: &str) -> Result<T, error::Error> {
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(part)?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+// Length of the `header.payload` part covered by the signature.
+fn header_payload_len(token: &str) -> usize {
+    token.rfind('.').unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use ring::rand::SystemRandom;
+    use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    const AUDIENCE: &str = "https://ingestor.example.com/push";
+    const EMAIL: &str = "pusher@project.iam.gserviceaccount.com";
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+    fn key_pair() -> RsaKeyPair {
+        let pem = fs::read_to_string(format!("{}/push_key.pem", FIXTURES)).unwrap();
+        let base64: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(base64)
+            .unwrap();
+        RsaKeyPair::from_pkcs8(&der).unwrap()
+    }
+
+    fn encode(value: &Value) -> String {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(value.to_string())
+    }
+
+    fn sign(header: Value, claims: Value) -> String {
+        let key_pair = key_pair();
+        let signed = format!("{}.{}", encode(&header), encode(&claims));
+        let mut signature = vec![0; key_pair.public().modulus_len()];
+        key_pair
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                signed.as_bytes(),
+                &mut signature,
+            )
+            .unwrap();
+        format!(
+            "{}.{}",
+            signed,
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn header() -> Value {
+        json!({"alg": "RS256", "kid": "test-key", "typ": "JWT"})
+    }
+
+    fn claims() -> Value {
+        json!({
+            "aud": AUDIENCE,
+            "iss": "https://accounts.google.com",
+            "iat": now(),
+            "exp": now() + 3600,
+            "email": EMAIL,
+            "email_verified": true,
+        })
+    }
+
+    fn with(mut value: Value, field: &str, replacement: Value) -> Value {
+        value[field] = replacement;
+        value
+    }
+
+    fn envelope(data: &str) -> Value {
+        json!({
+            "message": {
+                "data": base64::engine::general_purpose::STANDARD.encode(data),
+                "attributes": {"source": "test"},
+                "messageId": "42",
+                "publishTime": "2024-01-01T00:00:00Z",
+            },
+            "subscription": "projects/project/subscriptions/push",
+            "deliveryAttempt": 1,
+        })
+    }
+
+    fn verifier() -> PushVerifier {
+        let jwks = Jwks::from_file(format!("{}/jwks.json", FIXTURES)).unwrap();
+        PushVerifier::new(AUDIENCE.to_string(), EMAIL.to_string(), jwks)
+    }
+
+    // Status of the push request, and the messages the handler received.
+    async fn push(token: Option<String>, body: Value) -> (StatusCode, Vec<String>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let handled = Arc::clone(&received);
+        let app = init_service(App::new().service(resource(
+            "/push",
+            verifier(),
+            move |message: PushMessage| {
+                let handled = Arc::clone(&handled);
+                async move {
+                    let data = message.message().decode().unwrap();
+                    let data = String::from_utf8(data).unwrap();
+                    handled.lock().unwrap().push(data.clone());
+                    match data.as_str() {
+                        "fail" => Err("Handler failed"),
+                        _ => Ok(()),
+                    }
+                }
+            },
+        )))
+        .await;
+
+        let mut req = TestRequest::post()
+            .uri("/push")
+            .set_payload(body.to_string());
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+        }
+        let resp = call_service(&app, req.to_request()).await;
+        let received = received.lock().unwrap().clone();
+        (resp.status(), received)
+    }
+
+    #[actix_web::test]
+    async fn hands_verified_message_to_handler() {
+        let token = sign(header(), claims());
+        let (status, received) = push(Some(token), envelope("hello")).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(received, ["hello"]);
+    }
+
+    #[test]
+    fn exposes_envelope_fields() {
+        let body = serde_json::to_vec(&envelope("hello")).unwrap();
+        let envelope: PushEnvelope = serde_json::from_slice(&body).unwrap();
+        assert_eq!(envelope.subscription, "projects/project/subscriptions/push");
+        assert_eq!(envelope.delivery_attempt, Some(1));
+        assert_eq!(envelope.message.message_id, "42");
+        assert_eq!(
+            envelope.message.publish_time.map(|t| t.unix_timestamp()),
+            Some(1_704_067_200)
+        );
+        assert_eq!(
+            envelope.message.encoded.attributes().unwrap()["source"],
+            "test"
+        );
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_tokens() {
+        let valid = sign(header(), claims());
+        let (signed, signature) = valid.rsplit_once('.').unwrap();
+        let (header_part, _) = signed.split_once('.').unwrap();
+        // Otherwise valid claims, but not the ones that were signed.
+        let tampered = format!(
+            "{}.{}.{}",
+            header_part,
+            encode(&with(claims(), "iat", json!(now() - 1))),
+            signature
+        );
+
+        let cases = vec![
+            ("missing bearer token", None),
+            ("invalid token signature", Some(tampered)),
+            (
+                "unexpected token audience",
+                Some(sign(
+                    header(),
+                    with(claims(), "aud", json!("https://other.example.com")),
+                )),
+            ),
+            (
+                "unexpected token issuer",
+                Some(sign(
+                    header(),
+                    with(claims(), "iss", json!("https://evil.example.com")),
+                )),
+            ),
+            (
+                "unexpected token service account",
+                Some(sign(
+                    header(),
+                    with(claims(), "email", json!("other@example.com")),
+                )),
+            ),
+            (
+                "unexpected token service account",
+                Some(sign(
+                    header(),
+                    with(claims(), "email_verified", json!(false)),
+                )),
+            ),
+            (
+                "token expired",
+                Some(sign(header(), with(claims(), "exp", json!(now() - 3600)))),
+            ),
+            (
+                "unsupported token algorithm",
+                Some(sign(with(header(), "alg", json!("HS256")), claims())),
+            ),
+            (
+                "unknown signing key",
+                Some(sign(with(header(), "kid", json!("other-key")), claims())),
+            ),
+        ];
+        for (reason, token) in cases {
+            if let Some(token) = &token {
+                match verifier().verify_token(token).await {
+                    Err(PushError::Unauthorized(e)) => assert_eq!(e, reason),
+                    other => panic!("Unexpected result for {}: {:?}", reason, other),
+                }
+            }
+            let (status, received) = push(token, envelope("hello")).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", reason);
+            assert!(received.is_empty(), "{}", reason);
+        }
+    }
+
+    #[actix_web::test]
+    async fn rejects_malformed_envelope() {
+        let token = sign(header(), claims());
+        let (status, received) = push(Some(token), json!({"message": "hello"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(received.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn nacks_when_handler_fails() {
+        let token = sign(header(), claims());
+        let (status, received) = push(Some(token), envelope("fail")).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(received, ["fail"]);
+    }
+
+    #[test]
+    fn skips_keys_other_than_rsa() {
+        let jwks = Jwks::from_file(format!("{}/jwks.json", FIXTURES)).unwrap();
+        let kids: Vec<&String> = jwks.keys.keys().collect();
+        assert_eq!(kids, ["test-key"]);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::error;
+use rand::Rng;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// How failed requests are retried.
+///
+/// Only errors for which [`error::Error::is_retryable`] holds are retried. The delay between
+/// attempts grows exponentially up to `max_backoff`, each actual delay being picked at random
+/// between zero and the current backoff to spread the retries of concurrent callers.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts made in total, the first one included.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// No retry is started once this long has passed since the first attempt.
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            deadline: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy making a single attempt.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    pub(crate) async fn retry<T, F, Fut>(&self, mut operation: F) -> Result<T, error::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, error::Error>>,
+    {
+        let start = Instant::now();
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+
+        loop {
+            let e = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            if attempt >= self.max_attempts || !e.is_retryable() {
+                return Err(e);
+            }
+
+            let delay = jitter(backoff);
+            if let Some(deadline) = self.deadline {
+                if start.elapsed() + delay > deadline {
+                    return Err(e);
+                }
+            }
+
+            log::debug!(
+                "Retrying request in {:?} (attempt {} failed: {})",
+                delay,
+                attempt,
+                e
+            );
+            tokio::time::sleep(delay).await;
+
+            backoff = backoff.mul_f64(self.multiplier).min(self.max_backoff);
+            attempt += 1;
+        }
+    }
+}
+
+fn jitter(backoff: Duration) -> Duration {
+    let millis = backoff.as_millis() as u64;
+    Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(max_attempts: u32, deadline: Option<Duration>) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            multiplier: 2.0,
+            deadline,
+        }
+    }
+
+    fn unavailable() -> error::Error {
+        error::Error::PubSub {
+            code: 503,
+            message: String::new(),
+            status: "UNAVAILABLE".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let attempts = AtomicU32::new(0);
+        let result = policy(5, None)
+            .retry(|| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err(unavailable()),
+                    _ => Ok("done"),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let attempts = AtomicU32::new(0);
+        let result = policy(4, None)
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(unavailable())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_errors() {
+        let attempts = AtomicU32::new(0);
+        let result = policy(4, None)
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(error::Error::PubSub {
+                    code: 404,
+                    message: String::new(),
+                    status: "NOT_FOUND".to_string(),
+                })
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_past_the_deadline() {
+        let attempts = AtomicU32::new(0);
+        let result = policy(100, Some(Duration::from_millis(30)))
+            .retry(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Err::<(), _>(unavailable())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,706 @@
+use crate::error;
+use crate::message::FromPubSubMessage;
+use crate::subscription::Subscription;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinHandle};
+use tokio::time::{self, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+/// Pub/Sub rejects acknowledge and modifyAckDeadline requests with too many ids.
+const MAX_IDS_PER_REQUEST: usize = 1000;
+const ACK_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+const PULL_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct SubscriberConfig {
+    /// Number of pull requests kept in flight.
+    pub pull_tasks: usize,
+    pub max_messages_per_pull: usize,
+    /// Pulling pauses while this many messages are being handled.
+    pub max_outstanding_messages: usize,
+    /// Pulling pauses while the messages being handled reach this size.
+    pub max_outstanding_bytes: usize,
+    /// Deadline requested for the messages, renewed until they are acked or nacked.
+    pub ack_deadline: Duration,
+    /// Messages held longer than this stop being renewed and get redelivered.
+    pub max_extension: Duration,
+}
+
+impl Default for SubscriberConfig {
+    fn default() -> Self {
+        SubscriberConfig {
+            pull_tasks: 2,
+            max_messages_per_pull: 100,
+            max_outstanding_messages: 1000,
+            max_outstanding_bytes: 100_000_000,
+            ack_deadline: Duration::from_secs(60),
+            max_extension: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl SubscriberConfig {
+    fn ack_deadline_seconds(&self) -> i32 {
+        self.ack_deadline.as_secs().clamp(10, 600) as i32
+    }
+
+    fn max_outstanding_bytes(&self) -> usize {
+        self.max_outstanding_bytes.clamp(1, Semaphore::MAX_PERMITS)
+    }
+}
+
+/// Message pulled by a [`Subscriber`], or the error converting it, and its lease.
+pub type Leased<T> = (Result<T, error::Error>, Lease);
+
+enum LeaseEvent {
+    Received(Vec<String>),
+    Ack(String),
+    Nack(String),
+}
+
+/// Hold on a message pulled by a [`Subscriber`].
+///
+/// Its ack deadline is extended until the lease is acked. Dropping it without acking nacks the
+/// message, making it available for redelivery right away.
+pub struct Lease {
+    ack_id: Option<String>,
+    events: mpsc::UnboundedSender<LeaseEvent>,
+    _permits: (OwnedSemaphorePermit, OwnedSemaphorePermit),
+}
+
+impl Lease {
+    pub fn ack_id(&self) -> &str {
+        self.ack_id.as_deref().unwrap_or_default()
+    }
+
+    /// Acknowledges the message with the next batch of acknowledgements.
+    pub fn ack(mut self) {
+        if let Some(id) = self.ack_id.take() {
+            let _ = self.events.send(LeaseEvent::Ack(id));
+        }
+    }
+
+    /// Makes the message available for redelivery with the next batch of nacks, same as
+    /// dropping the lease.
+    pub fn nack(mut self) {
+        if let Some(id) = self.ack_id.take() {
+            let _ = self.events.send(LeaseEvent::Nack(id));
+        }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if let Some(id) = self.ack_id.take() {
+            let _ = self.events.send(LeaseEvent::Nack(id));
+        }
+    }
+}
+
+struct FlowControl {
+    messages: Arc<Semaphore>,
+    bytes: Arc<Semaphore>,
+    max_bytes: usize,
+}
+
+/// Pulls messages from a subscription with concurrent requests.
+///
+/// Pulling pauses once the outstanding messages reach the limits of the config, and the ack
+/// deadline of every outstanding message is extended in the background. Once the cancellation
+/// token fires no new pull is sent, the messages already pulled are still handed out. A pull
+/// request in flight at that moment is abandoned and its messages redelivered once their
+/// deadline expires. Must be used from within a Tokio runtime.
+pub struct Subscriber {
+    subscription: Subscription,
+    config: SubscriberConfig,
+}
+
+impl Subscriber {
+    pub(crate) fn new(subscription: Subscription, config: SubscriberConfig) -> Self {
+        Subscriber {
+            subscription,
+            config,
+        }
+    }
+
+    /// Runs `handler` on every message until `cancel` fires, then waits for the running
+    /// handlers and the last acknowledgements.
+    ///
+    /// A message is acked when its handler succeeds and nacked when it fails or the message
+    /// cannot be converted.
+    pub async fn run<T, H, F, E>(&self, cancel: CancellationToken, handler: H)
+    where
+        T: FromPubSubMessage + Send + 'static,
+        H: Fn(T) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display + Send + 'static,
+    {
+        let (messages, leases) = self.start::<T>(cancel);
+        let handler = Arc::new(handler);
+
+        receiver_stream(messages)
+            .for_each_concurrent(None, |(message, lease)| {
+                let handler = Arc::clone(&handler);
+                async move {
+                    let message = match message {
+                        Ok(message) => message,
+                        Err(e) => {
+                            log::error!("Failed converting message {}: {}", lease.ack_id(), e);
+                            return lease.nack();
+                        }
+                    };
+                    match task::spawn(handler(message)).await {
+                        Ok(Ok(())) => lease.ack(),
+                        Ok(Err(e)) => {
+                            log::error!("Failed handling message {}: {}", lease.ack_id(), e);
+                            lease.nack();
+                        }
+                        Err(e) => {
+                            log::error!("Handler of message {} panicked: {}", lease.ack_id(), e);
+                            lease.nack();
+                        }
+                    }
+                }
+            })
+            .await;
+
+        let _ = leases.await;
+    }
+
+    /// Streams the pulled messages, with the lease to ack each of them.
+    ///
+    /// The stream ends once `cancel` fired and every message pulled before was handed out.
+    pub fn stream<T>(
+        &self,
+        cancel: CancellationToken,
+    ) -> impl Stream<Item = Leased<T>> + Send + 'static
+    where
+        T: FromPubSubMessage + Send + 'static,
+    {
+        receiver_stream(self.start(cancel).0)
+    }
+
+    fn start<T>(
+        &self,
+        cancel: CancellationToken,
+    ) -> (mpsc::UnboundedReceiver<Leased<T>>, JoinHandle<()>)
+    where
+        T: FromPubSubMessage + Send + 'static,
+    {
+        let (events, events_receiver) = mpsc::unbounded_channel();
+        let (messages, messages_receiver) = mpsc::unbounded_channel();
+        let flow_control = Arc::new(FlowControl {
+            messages: Arc::new(Semaphore::new(self.config.max_outstanding_messages.max(1))),
+            bytes: Arc::new(Semaphore::new(self.config.max_outstanding_bytes())),
+            max_bytes: self.config.max_outstanding_bytes(),
+        });
+
+        for _ in 0..self.config.pull_tasks.max(1) {
+            task::spawn(pull(
+                self.subscription.clone(),
+                self.config.clone(),
+                Arc::clone(&flow_control),
+                events.clone(),
+                messages.clone(),
+                cancel.clone(),
+            ));
+        }
+
+        let leases = task::spawn(manage_leases(
+            self.subscription.clone(),
+            self.config.clone(),
+            events_receiver,
+        ));
+
+        (messages_receiver, leases)
+    }
+}
+
+fn receiver_stream<T: Send + 'static>(
+    receiver: mpsc::UnboundedReceiver<T>,
+) -> impl Stream<Item = T> + Send + 'static {
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|item| (item, receiver))
+    })
+}
+
+async fn pull<T: FromPubSubMessage>(
+    subscription: Subscription,
+    config: SubscriberConfig,
+    flow_control: Arc<FlowControl>,
+    events: mpsc::UnboundedSender<LeaseEvent>,
+    messages: mpsc::UnboundedSender<Leased<T>>,
+    cancel: CancellationToken,
+) {
+    loop {
+        // Holding one permit before pulling keeps a full subscriber from pulling more.
+        let first = tokio::select! {
+            permit = Arc::clone(&flow_control.messages).acquire_owned() => permit.unwrap(),
+            _ = cancel.cancelled() => return,
+        };
+        let max_messages = (flow_control.messages.available_permits() + 1)
+            .min(config.max_messages_per_pull.max(1));
+
+        let pulled = tokio::select! {
+            pulled = subscription.receive_messages(max_messages as i32) => pulled,
+            _ = cancel.cancelled() => return,
+        };
+        let pulled = match pulled {
+            Ok(pulled) => pulled,
+            Err(e) => {
+                log::error!("Failed to pull PubSub messages: {}", e);
+                tokio::select! {
+                    _ = time::sleep(PULL_ERROR_BACKOFF) => continue,
+                    _ = cancel.cancelled() => return,
+                }
+            }
+        };
+        if pulled.is_empty() {
+            continue;
+        }
+
+        let ids = pulled.iter().map(|m| m.ack_id().to_string()).collect();
+        let _ = events.send(LeaseEvent::Received(ids));
+
+        let mut first = Some(first);
+        for message in pulled {
+            let permit = match first.take() {
+                Some(permit) => permit,
+                None => Arc::clone(&flow_control.messages)
+                    .acquire_owned()
+                    .await
+                    .unwrap(),
+            };
+            let size = message.message().size().clamp(1, flow_control.max_bytes);
+            let bytes = Arc::clone(&flow_control.bytes)
+                .acquire_many_owned(size.min(u32::MAX as usize) as u32)
+                .await
+                .unwrap();
+
+            let lease = Lease {
+                ack_id: Some(message.ack_id().to_string()),
+                events: events.clone(),
+                _permits: (permit, bytes),
+            };
+            // The lease of a message nobody receives anymore is dropped, nacking it.
+            if messages.send((T::from_received(&message), lease)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Stops once the pull tasks and every lease are gone.
+async fn manage_leases(
+    subscription: Subscription,
+    config: SubscriberConfig,
+    mut events: mpsc::UnboundedReceiver<LeaseEvent>,
+) {
+    let deadline = config.ack_deadline_seconds();
+    let mut leases: HashMap<String, Instant> = HashMap::new();
+    let mut acks = Vec::new();
+    let mut nacks = Vec::new();
+
+    let mut flush = time::interval(ACK_FLUSH_INTERVAL);
+    let extend_every = Duration::from_secs(deadline as u64 / 2);
+    let mut extend = time::interval_at(Instant::now() + extend_every, extend_every);
+    extend.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(LeaseEvent::Received(ids)) => {
+                    let now = Instant::now();
+                    leases.extend(ids.iter().map(|id| (id.clone(), now)));
+                    modify_ack_deadline(&subscription, ids, deadline).await;
+                }
+                Some(LeaseEvent::Ack(id)) => {
+                    leases.remove(&id);
+                    acks.push(id);
+                }
+                Some(LeaseEvent::Nack(id)) => {
+                    leases.remove(&id);
+                    nacks.push(id);
+                }
+                None => break,
+            },
+            _ = flush.tick() => {
+                flush_acks(&subscription, &mut acks, &mut nacks).await;
+            }
+            _ = extend.tick() => {
+                leases.retain(|id, received| {
+                    let keep = received.elapsed() < config.max_extension;
+                    if !keep {
+                        log::warn!("Lease of message {} expired, it will be redelivered", id);
+                    }
+                    keep
+                });
+                let ids = leases.keys().cloned().collect();
+                modify_ack_deadline(&subscription, ids, deadline).await;
+            }
+        }
+    }
+
+    flush_acks(&subscription, &mut acks, &mut nacks).await;
+}
+
+async fn flush_acks(subscription: &Subscription, acks: &mut Vec<String>, nacks: &mut Vec<String>) {
+    for ids in std::mem::take(acks).chunks(MAX_IDS_PER_REQUEST) {
+        subscription.acknowledge_messages(ids.to_vec()).await;
+    }
+    modify_ack_deadline(subscription, std::mem::take(nacks), 0).await;
+}
+
+async fn modify_ack_deadline(subscription: &Subscription, ids: Vec<String>, seconds: i32) {
+    for ids in ids.chunks(MAX_IDS_PER_REQUEST) {
+        if let Err(e) = subscription
+            .modify_ack_deadline(ids.to_vec(), seconds)
+            .await
+        {
+            log::error!(
+                "Failed modifying ack deadline of {} messages: {}",
+                ids.len(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::EncodedMessage;
+    use crate::testing::{Recorded, Response, StandIn};
+    use crate::{ClientBuilder, RetryPolicy};
+    use base64::Engine;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    struct Text(String);
+
+    impl FromPubSubMessage for Text {
+        fn from(message: EncodedMessage) -> Result<Self, error::Error> {
+            let data = message.decode()?;
+            Ok(Text(String::from_utf8_lossy(&data).into_owned()))
+        }
+    }
+
+    fn message(ack_id: &str, data: &str) -> serde_json::Value {
+        json!({
+            "ackId": ack_id,
+            "message": {
+                "data": base64::engine::general_purpose::STANDARD.encode(data),
+                "messageId": ack_id,
+            },
+        })
+    }
+
+    // Answers each pull with the messages `next` gives for its `maxMessages`, an empty pull
+    // being answered after a while as the server does.
+    fn pubsub_server<F>(next: F) -> StandIn
+    where
+        F: Fn(usize) -> Vec<serde_json::Value> + Send + Sync + 'static,
+    {
+        StandIn::start(move |request| {
+            if !request.path.ends_with(":pull") {
+                return Response::json(json!({}));
+            }
+            let max_messages = request.json()["maxMessages"].as_u64().unwrap();
+            let messages = next(max_messages as usize);
+            if messages.is_empty() {
+                std::thread::sleep(Duration::from_millis(50));
+                return Response::json(json!({}));
+            }
+            Response::json(json!({ "receivedMessages": messages }))
+        })
+    }
+
+    // Hands out `messages` once, then nothing.
+    fn queued_server(messages: Vec<serde_json::Value>) -> StandIn {
+        let messages = Mutex::new(messages);
+        pubsub_server(move |max_messages| {
+            let mut messages = messages.lock().unwrap();
+            let count = max_messages.min(messages.len());
+            messages.drain(..count).collect()
+        })
+    }
+
+    // Hands out as many messages as asked for, forever.
+    fn endless_server(data: &'static str) -> StandIn {
+        let next_id = AtomicUsize::new(0);
+        pubsub_server(move |max_messages| {
+            (0..max_messages)
+                .map(|_| {
+                    let id = next_id.fetch_add(1, Ordering::SeqCst);
+                    message(&format!("ack-{}", id), data)
+                })
+                .collect()
+        })
+    }
+
+    async fn subscriber(server: &StandIn, config: SubscriberConfig) -> Subscriber {
+        let client = ClientBuilder::new()
+            .emulator(server.host())
+            .project("project")
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .await
+            .unwrap();
+        client
+            .subscribe("subscription".to_string())
+            .subscriber(config)
+    }
+
+    fn ack_ids(request: &Recorded, field: &str) -> Vec<String> {
+        serde_json::from_value(request.json()[field].clone()).unwrap()
+    }
+
+    fn acked_ids(server: &StandIn) -> Vec<String> {
+        server
+            .requests_to(":acknowledge")
+            .iter()
+            .flat_map(|request| ack_ids(request, "ack_ids"))
+            .collect()
+    }
+
+    // Largest number of handlers running at once over the first 20 messages.
+    async fn most_handled_at_once(config: SubscriberConfig) -> usize {
+        let server = endless_server("message");
+        let subscriber = subscriber(&server, config).await;
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let handled = Arc::new(AtomicUsize::new(0));
+        let cancel = CancellationToken::new();
+
+        let run = subscriber.run(cancel.clone(), {
+            let most = Arc::clone(&most);
+            move |_: Text| {
+                let running = Arc::clone(&running);
+                let most = Arc::clone(&most);
+                let handled = Arc::clone(&handled);
+                let cancel = cancel.clone();
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    time::sleep(Duration::from_millis(20)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    if handled.fetch_add(1, Ordering::SeqCst) + 1 == 20 {
+                        cancel.cancel();
+                    }
+                    Ok::<_, String>(())
+                }
+            }
+        });
+        time::timeout(Duration::from_secs(10), run)
+            .await
+            .expect("Subscriber kept running once cancelled");
+        most.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn acks_handled_messages_and_nacks_failed_ones() {
+        let server = queued_server(vec![message("ack-1", "good"), message("ack-2", "bad")]);
+        let subscriber = subscriber(
+            &server,
+            SubscriberConfig {
+                pull_tasks: 1,
+                ..SubscriberConfig::default()
+            },
+        )
+        .await;
+
+        let cancel = CancellationToken::new();
+        let run = tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                subscriber
+                    .run(cancel, |message: Text| async move {
+                        match message.0.as_str() {
+                            "good" => Ok(()),
+                            other => Err(format!("Cannot handle {}", other)),
+                        }
+                    })
+                    .await
+            }
+        });
+
+        let is_nack = |request: &Recorded| request.json()["ackDeadlineSeconds"] == 0;
+        for _ in 0..100 {
+            let acked = !server.requests_to(":acknowledge").is_empty();
+            let nacked = server.requests_to(":modifyAckDeadline").iter().any(is_nack);
+            if acked && nacked {
+                break;
+            }
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        cancel.cancel();
+        time::timeout(Duration::from_secs(5), run)
+            .await
+            .expect("Subscriber kept running once cancelled")
+            .unwrap();
+
+        let path = "/v1/projects/project/subscriptions/subscription";
+        let acks = server.requests_to(":acknowledge");
+        assert_eq!(acks.len(), 1);
+        assert_eq!(acks[0].path, format!("{}:acknowledge", path));
+        assert_eq!(ack_ids(&acks[0], "ack_ids"), ["ack-1"]);
+
+        let deadlines = server.requests_to(":modifyAckDeadline");
+        let leased = &deadlines[0];
+        assert_eq!(leased.json()["ackDeadlineSeconds"], 60);
+        assert_eq!(ack_ids(leased, "ackIds"), ["ack-1", "ack-2"]);
+        let nacks: Vec<_> = deadlines.iter().filter(|r| is_nack(r)).collect();
+        assert_eq!(nacks.len(), 1);
+        assert_eq!(ack_ids(nacks[0], "ackIds"), ["ack-2"]);
+    }
+
+    #[tokio::test]
+    async fn handles_no_more_than_the_outstanding_messages() {
+        let most = most_handled_at_once(SubscriberConfig {
+            max_messages_per_pull: 10,
+            max_outstanding_messages: 3,
+            ..SubscriberConfig::default()
+        })
+        .await;
+        assert_eq!(most, 3);
+    }
+
+    #[tokio::test]
+    async fn handles_no_more_than_the_outstanding_bytes() {
+        // Two of the messages fit, each being 12 bytes once encoded.
+        let most = most_handled_at_once(SubscriberConfig {
+            max_messages_per_pull: 10,
+            max_outstanding_bytes: 30,
+            ..SubscriberConfig::default()
+        })
+        .await;
+        assert_eq!(most, 2);
+    }
+
+    #[tokio::test]
+    async fn extends_deadline_of_slow_handlers() {
+        let server = Arc::new(queued_server(vec![message("ack-1", "slow")]));
+        let subscriber = subscriber(
+            &server,
+            SubscriberConfig {
+                pull_tasks: 1,
+                ack_deadline: Duration::from_secs(10),
+                ..SubscriberConfig::default()
+            },
+        )
+        .await;
+
+        // The handler runs until the lease is extended, half the deadline in.
+        let cancel = CancellationToken::new();
+        let run = subscriber.run(cancel.clone(), {
+            let server = Arc::clone(&server);
+            move |_: Text| {
+                let server = Arc::clone(&server);
+                let cancel = cancel.clone();
+                async move {
+                    while server.requests_to(":modifyAckDeadline").len() < 2 {
+                        time::sleep(Duration::from_millis(100)).await;
+                    }
+                    cancel.cancel();
+                    Ok::<_, String>(())
+                }
+            }
+        });
+        time::timeout(Duration::from_secs(8), run)
+            .await
+            .expect("Lease was not extended");
+
+        let requests: Vec<_> = server
+            .requests()
+            .into_iter()
+            .filter(|request| !request.path.ends_with(":pull"))
+            .collect();
+        let paths: Vec<_> = requests
+            .iter()
+            .map(|request| request.path.rsplit(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            ["modifyAckDeadline", "modifyAckDeadline", "acknowledge"]
+        );
+        for extension in &requests[..2] {
+            assert_eq!(extension.json()["ackDeadlineSeconds"], 10);
+            assert_eq!(ack_ids(extension, "ackIds"), ["ack-1"]);
+        }
+        assert_eq!(ack_ids(&requests[2], "ack_ids"), ["ack-1"]);
+    }
+
+    #[tokio::test]
+    async fn stops_pulling_once_cancelled() {
+        let server = queued_server(Vec::new());
+        let subscriber = subscriber(&server, SubscriberConfig::default()).await;
+
+        let cancel = CancellationToken::new();
+        let run = subscriber.run(cancel.clone(), |_: Text| async { Ok::<_, String>(()) });
+        let stop = async {
+            time::sleep(Duration::from_millis(200)).await;
+            cancel.cancel();
+        };
+        time::timeout(Duration::from_secs(5), futures::future::join(run, stop))
+            .await
+            .expect("Subscriber kept running once cancelled");
+
+        let pulls = server.requests_to(":pull").len();
+        assert!(pulls > 0);
+        time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(server.requests_to(":pull").len(), pulls);
+    }
+
+    #[tokio::test]
+    async fn streams_messages_with_their_lease() {
+        let server = queued_server(vec![
+            message("ack-1", "one"),
+            message("ack-2", "two"),
+            message("ack-3", "three"),
+        ]);
+        let subscriber = subscriber(
+            &server,
+            SubscriberConfig {
+                pull_tasks: 1,
+                ..SubscriberConfig::default()
+            },
+        )
+        .await;
+
+        let cancel = CancellationToken::new();
+        let mut stream = Box::pin(subscriber.stream::<Text>(cancel.clone()));
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let (message, lease) = stream.next().await.unwrap();
+            received.push((message.unwrap().0, lease.ack_id().to_string()));
+            lease.ack();
+        }
+        assert_eq!(
+            received,
+            [
+                ("one".to_string(), "ack-1".to_string()),
+                ("two".to_string(), "ack-2".to_string()),
+                ("three".to_string(), "ack-3".to_string()),
+            ]
+        );
+
+        cancel.cancel();
+        let end = time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert!(matches!(end, Ok(None)), "Stream kept going once cancelled");
+
+        for _ in 0..100 {
+            if acked_ids(&server).len() == 3 {
+                break;
+            }
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(acked_ids(&server), ["ack-1", "ack-2", "ack-3"]);
+    }
+}
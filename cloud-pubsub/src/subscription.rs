@@ -1,17 +1,10 @@
+use crate::admin::SubscriptionConfig;
 use crate::client::Client;
 use crate::error;
-use crate::message::{FromPubSubMessage, Message};
-use hyper::body::Buf;
-use hyper::{Method, StatusCode};
-use lazy_static::lazy_static;
+use crate::message::{FromPubSubMessage, Message, ReceivedMessage};
+use crate::subscriber::{Subscriber, SubscriberConfig};
+use hyper::Method;
 use serde_derive::{Deserialize, Serialize};
-use std::env;
-
-lazy_static! {
-    static ref PUBSUB_HOST: String = env::var("PUBSUB_EMULATOR_HOST")
-        .map(|host| format!("http://{}", host))
-        .unwrap_or_else(|_| String::from("https://pubsub.googleapis.com"));
-}
 
 #[derive(Deserialize)]
 struct Response {
@@ -26,11 +19,20 @@ struct AckRequest {
     ack_ids: Vec<String>,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ModifyAckDeadlineRequest {
+    ack_ids: Vec<String>,
+    ack_deadline_seconds: i32,
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Subscription {
     #[serde(skip_serializing)]
     pub name: String,
     pub topic: Option<String>,
+    #[serde(flatten)]
+    pub config: SubscriptionConfig,
 
     #[serde(skip)]
     pub(crate) client: Option<Client>,
@@ -38,54 +40,69 @@ pub struct Subscription {
 
 impl Subscription {
     pub async fn acknowledge_messages(&self, ids: Vec<String>) {
+        if let Err(e) = self.acknowledge(ids).await {
+            log::error!("Failed ACK: {}", e);
+        }
+    }
+
+    pub(crate) async fn acknowledge(&self, ids: Vec<String>) -> Result<(), error::Error> {
         let client = self
             .client
             .as_ref()
             .expect("Subscription was not created using a client");
 
-        let uri: hyper::Uri = format!("{}/v1/{}:acknowledge", *PUBSUB_HOST, self.name)
-            .parse()
-            .unwrap();
+        let path = format!("{}:acknowledge", self.name);
 
         let json = serde_json::to_string(&AckRequest { ack_ids: ids }).unwrap();
 
-        let mut req = client.request(Method::POST, json);
-        *req.uri_mut() = uri.clone();
-
-        if let Err(e) = client.hyper_client().request(req).await {
-            log::error!("Failed ACK: {}", e);
-        }
+        client.perform(Method::POST, &path, json).await?;
+        Ok(())
     }
 
     pub async fn get_messages<T: FromPubSubMessage>(
         &self,
         max_messages: i32,
     ) -> Result<Vec<(Result<T, error::Error>, String)>, error::Error> {
+        let messages = self
+            .receive_messages(max_messages)
+            .await?
+            .into_iter()
+            .map(|m| (T::from_received(&m), m.ack_id().to_string()))
+            .collect();
+        Ok(messages)
+    }
+
+    /// Consumes the subscription with concurrent pulls, see [`Subscriber`].
+    pub fn subscriber(&self, config: SubscriberConfig) -> Subscriber {
+        Subscriber::new(self.clone(), config)
+    }
+
+    /// Pulls messages that can be acked, nacked or extended on their own.
+    pub async fn receive_messages(
+        &self,
+        max_messages: i32,
+    ) -> Result<Vec<ReceivedMessage>, error::Error> {
         let client = self
             .client
             .as_ref()
             .expect("Subscription was not created using a client");
 
-        let uri: hyper::Uri = format!("{}/v1/{}:pull", *PUBSUB_HOST, self.name)
-            .parse()
-            .unwrap();
+        let path = format!("{}:pull", self.name);
 
         let json = format!("{{\"maxMessages\": {}}}", max_messages);
 
-        let mut req = client.request(Method::POST, json);
-        *req.uri_mut() = uri.clone();
-
-        let response = client.hyper_client().request(req).await?;
-
-        if response.status() == StatusCode::NOT_FOUND {
-            return Err(error::Error::PubSub {
-                code: 404,
-                status: "Subscription Not Found".to_string(),
-                message: self.name.clone(),
-            });
-        }
-        let body = hyper::body::aggregate(response).await?;
-        let response: Response = serde_json::from_reader(body.reader())?;
+        let body = match client.perform(Method::POST, &path, json).await {
+            Ok(body) => body,
+            Err(error::Error::PubSub { code: 404, .. }) => {
+                return Err(error::Error::PubSub {
+                    code: 404,
+                    status: "Subscription Not Found".to_string(),
+                    message: self.name.clone(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        let response: Response = serde_json::from_slice(&body)?;
         if let Some(e) = response.error {
             return Err(e);
         }
@@ -93,28 +110,48 @@ impl Subscription {
             .received_messages
             .unwrap_or_default()
             .into_iter()
-            .map(|m| (T::from(m.message), m.ack_id))
+            .map(|m| ReceivedMessage::new(self.clone(), m))
             .collect();
         Ok(messages)
     }
 
-    pub async fn destroy(self) -> Result<(), error::Error> {
+    /// Sets the deadline of the messages to `seconds` from now, `0` making them available
+    /// for redelivery right away.
+    pub async fn modify_ack_deadline(
+        &self,
+        ids: Vec<String>,
+        seconds: i32,
+    ) -> Result<(), error::Error> {
         let client = self
             .client
+            .as_ref()
             .expect("Subscription was not created using a client");
 
-        let uri: hyper::Uri = format!("{}/v1/{}", *PUBSUB_HOST, self.name)
-            .parse()
-            .unwrap();
+        let path = format!("{}:modifyAckDeadline", self.name);
 
-        let mut req = client.request(Method::DELETE, "");
-        *req.uri_mut() = uri.clone();
+        let json = serde_json::to_string(&ModifyAckDeadlineRequest {
+            ack_ids: ids,
+            ack_deadline_seconds: seconds,
+        })?;
 
-        if let Err(e) = client.hyper_client().request(req).await {
-            Err(e.into())
-        } else {
-            Ok(())
-        }
+        client.perform(Method::POST, &path, json).await?;
+        Ok(())
+    }
+
+    /// Makes the messages available for redelivery right away.
+    pub async fn nack(&self, ids: Vec<String>) -> Result<(), error::Error> {
+        self.modify_ack_deadline(ids, 0).await
+    }
+
+    pub async fn destroy(self) -> Result<(), error::Error> {
+        let client = self
+            .client
+            .expect("Subscription was not created using a client");
+
+        client
+            .perform(Method::DELETE, &self.name, String::new())
+            .await?;
+        Ok(())
     }
 
     pub fn client(&self) -> &Client {
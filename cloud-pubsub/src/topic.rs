@@ -1,25 +1,20 @@
+use crate::admin::{SubscriptionConfig, TopicConfig};
 use crate::client::Client;
 use crate::error;
+use crate::publisher::{Publisher, PublisherConfig};
 use crate::subscription::*;
 use crate::EncodedMessage;
-use hyper::body::Buf;
-use hyper::{Method, StatusCode};
-use lazy_static::lazy_static;
+use hyper::Method;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
-use std::env;
 
-lazy_static! {
-    static ref PUBSUB_HOST: String = env::var("PUBSUB_EMULATOR_HOST")
-        .map(|host| format!("http://{}", host))
-        .unwrap_or_else(|_| String::from("https://pubsub.googleapis.com"));
-}
-
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Topic {
     pub name: String,
+    #[serde(flatten)]
+    pub config: TopicConfig,
 
     #[serde(skip)]
     pub(crate) client: Option<Client>,
@@ -43,15 +38,14 @@ impl Topic {
         let new_subscription = Subscription {
             name: self.new_subscription_name(),
             topic: Some(self.name.clone()),
+            config: SubscriptionConfig::default(),
             client: None,
         };
 
-        let uri: hyper::Uri = format!("{}/v1/{}", *PUBSUB_HOST, new_subscription.name)
-            .parse()
-            .unwrap();
+        let path = new_subscription.name.clone();
 
         let mut sub = self
-            .perform_request::<Subscription, Subscription>(uri, Method::PUT, new_subscription)
+            .perform_request::<Subscription, Subscription>(&path, Method::PUT, new_subscription)
             .await?;
 
         sub.client = client.clone();
@@ -62,32 +56,40 @@ impl Topic {
         &self,
         data: T,
     ) -> Result<PublishMessageResponse, error::Error> {
-        self.publish_message(EncodedMessage::new(&data, None, None)).await
+        self.publish_message(EncodedMessage::new(&data, None, None))
+            .await
     }
 
     pub async fn publish_message(
         &self,
         message: EncodedMessage,
     ) -> Result<PublishMessageResponse, error::Error> {
-        let uri: hyper::Uri = format!("{}/v1/{}:publish", *PUBSUB_HOST, self.name)
-            .parse()
-            .unwrap();
+        self.publish_messages(vec![message]).await
+    }
 
-        let payload = PublishMessageRequest {
-            messages: vec![message],
-        };
+    pub async fn publish_messages(
+        &self,
+        messages: Vec<EncodedMessage>,
+    ) -> Result<PublishMessageResponse, error::Error> {
+        let path = format!("{}:publish", self.name);
+
+        let payload = PublishMessageRequest { messages };
 
         self.perform_request::<PublishMessageRequest, PublishMessageResponse>(
-            uri,
+            &path,
             Method::POST,
             payload,
         )
         .await
     }
 
+    pub fn publisher(&self, config: PublisherConfig) -> Publisher {
+        Publisher::new(self.clone(), config)
+    }
+
     async fn perform_request<T: serde::Serialize, U: DeserializeOwned + Clone>(
         &self,
-        uri: hyper::Uri,
+        path: &str,
         method: Method,
         data: T,
     ) -> Result<U, error::Error> {
@@ -97,31 +99,14 @@ impl Topic {
             .expect("Topic must be created using a client");
 
         let json = serde_json::to_string(&data).expect("Failed to serialize request body.");
-        let mut req = client.request(method, json);
-        *req.uri_mut() = uri;
-
-        let response = client.hyper_client().request(req).await?;
-        match response.status() {
-            StatusCode::NOT_FOUND => Err(error::Error::PubSub {
+        match client.perform(method, path, json).await {
+            Ok(body) => serde_json::from_slice(&body).map_err(|e| e.into()),
+            Err(error::Error::PubSub { code: 404, .. }) => Err(error::Error::PubSub {
                 code: 404,
                 status: "Topic Not Found".to_string(),
                 message: self.name.clone(),
             }),
-            StatusCode::OK => {
-                let body = hyper::body::aggregate(response).await?;
-                serde_json::from_reader(body.reader()).map_err(|e| e.into())
-            }
-            code => {
-                let body = hyper::body::aggregate(response).await?;
-                let mut buf = String::new();
-                use std::io::Read;
-                body.reader().read_to_string(&mut buf)?;
-                Err(error::Error::PubSub {
-                    code: code.as_u16() as i32,
-                    status: "Error occurred attempting to subscribe".to_string(),
-                    message: buf,
-                })
-            }
+            Err(e) => Err(e),
         }
     }
 
@@ -4,13 +4,43 @@ use serde::Deserialize;
 pub struct IngestorConfig {
     #[serde(default = "default_host")]
     pub host: String,
+    /// Webhook sources to mount, e.g. `todoist,github`.
+    #[serde(default = "default_sources")]
+    pub sources: Vec<String>,
+    /// Directory of the outbox, events are published directly when
+    /// unset.
+    pub outbox_dir: Option<String>,
+    #[serde(default = "default_outbox_segment_bytes")]
+    pub outbox_segment_bytes: u64,
+    /// Sink of the sources without their own, e.g. `pubsub`, or
+    /// `pubsub+http` to send the events to both.
+    #[serde(default = "default_sink")]
+    pub sink: String,
+    /// Sinks of specific sources, e.g. `github=kafka,todoist=stdout`.
+    #[serde(default)]
+    pub source_sinks: Vec<String>,
 }
 
+#[cfg(feature = "pubsub")]
 #[derive(Deserialize, Clone)]
 pub struct GoogleConfig {
-    pub application_credentials: String,
+    /// Service account key, the Application Default Credentials are
+    /// used when unset.
+    pub application_credentials: Option<String>,
 }
 
 fn default_host() -> String {
     "0.0.0.0:8080".to_string()
 }
+
+fn default_sources() -> Vec<String> {
+    vec!["todoist".to_string()]
+}
+
+fn default_outbox_segment_bytes() -> u64 {
+    16 * 1024 * 1024
+}
+
+fn default_sink() -> String {
+    "pubsub".to_string()
+}
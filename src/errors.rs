@@ -0,0 +1,94 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt;
+
+/// Failure of one step of a webhook ingestion.
+///
+/// Each variant maps to its own status code so the sender can tell
+/// whether retrying makes sense.
+#[derive(Debug)]
+pub enum IngestError {
+    /// Missing or invalid signature.
+    Unauthorized(anyhow::Error),
+    /// The body or one of its fields could not be decoded.
+    MalformedPayload(anyhow::Error),
+    /// The source API used for enrichment failed.
+    Upstream(anyhow::Error),
+    /// The event could not be handed to the sink.
+    Publish(anyhow::Error),
+}
+
+impl IngestError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IngestError::Unauthorized(_) => "unauthorized",
+            IngestError::MalformedPayload(_) => "malformed_payload",
+            IngestError::Upstream(_) => "upstream",
+            IngestError::Publish(_) => "publish",
+        }
+    }
+
+    pub fn log(&self, source: &str) {
+        let level = match self {
+            IngestError::Unauthorized(_)
+            | IngestError::MalformedPayload(_) => log::Level::Warn,
+            IngestError::Upstream(_) | IngestError::Publish(_) => {
+                log::Level::Error
+            }
+        };
+        log::log!(
+            level,
+            "webhook rejected: source={}, kind={}, status={}, error={:#}",
+            source,
+            self.kind(),
+            self.status_code().as_u16(),
+            self.inner()
+        );
+    }
+
+    fn inner(&self) -> &anyhow::Error {
+        match self {
+            IngestError::Unauthorized(e)
+            | IngestError::MalformedPayload(e)
+            | IngestError::Upstream(e)
+            | IngestError::Publish(e) => e,
+        }
+    }
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IngestError::Unauthorized(e) => {
+                write!(f, "Unauthorized({})", e)
+            }
+            IngestError::MalformedPayload(e) => {
+                write!(f, "MalformedPayload({})", e)
+            }
+            IngestError::Upstream(e) => write!(f, "Upstream({})", e),
+            IngestError::Publish(e) => write!(f, "Publish({})", e),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+impl ResponseError for IngestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IngestError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            IngestError::MalformedPayload(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            IngestError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            IngestError::Publish(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        // Details stay in the logs, the caller only gets the category.
+        HttpResponse::build(self.status_code()).body(self.kind())
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+use crate::errors::IngestError;
+use crate::outbox::Outbox;
+use crate::sinks::EventSink;
+
+/// Event accepted by a webhook, ready to be handed to its sink.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IngestedEvent {
+    pub source: String,
+    pub topic: String,
+    #[serde(with = "base64_bytes")]
+    pub payload: Vec<u8>,
+    pub attributes: HashMap<String, String>,
+    pub ordering_key: Option<String>,
+    /// Same for every delivery of the event by its source, when the
+    /// source gives one.
+    #[serde(default)]
+    pub dedup_id: Option<String>,
+    /// Set when the webhook was accepted, events written to the outbox
+    /// before it existed getting the time they are read back.
+    #[serde(
+        with = "time::serde::rfc3339",
+        default = "OffsetDateTime::now_utc"
+    )]
+    pub received_at: OffsetDateTime,
+}
+
+impl IngestedEvent {
+    /// Attributes sent along the payload by the sinks without a
+    /// field of their own for the dedup id.
+    #[cfg(any(
+        feature = "pubsub",
+        feature = "kafka",
+        feature = "redis"
+    ))]
+    pub fn message_attributes(&self) -> HashMap<String, String> {
+        let mut attributes = self.attributes.clone();
+        if let Some(id) = &self.dedup_id {
+            attributes.insert("dedup_id".to_string(), id.clone());
+        }
+        attributes
+    }
+
+    pub fn format_attributes(&self) -> String {
+        let mut pairs: Vec<String> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        pairs.sort();
+        pairs.join(", ")
+    }
+}
+
+/// How an accepted event leaves the webhook handler.
+#[derive(Clone)]
+pub enum Delivery {
+    /// Published before answering the webhook.
+    Direct(Arc<dyn EventSink>),
+    /// Persisted in the outbox, published later by its drainer.
+    Outbox(Arc<Outbox>),
+}
+
+impl Delivery {
+    pub async fn deliver(
+        &self,
+        event: &IngestedEvent,
+    ) -> Result<(), IngestError> {
+        match self {
+            Delivery::Direct(sink) => {
+                let id = sink
+                    .publish(event)
+                    .await
+                    .map_err(IngestError::Publish)?;
+                log::info!(
+                    "message published: source={}, topic={}, id={}, {}",
+                    event.source,
+                    event.topic,
+                    id,
+                    event.format_attributes()
+                );
+            }
+            Delivery::Outbox(outbox) => {
+                // The append waits for the disk, away from the worker
+                // serving the webhooks.
+                let outbox = outbox.clone();
+                let appended = event.clone();
+                actix_web::web::block(move || {
+                    outbox.append(&appended)
+                })
+                .await
+                .map_err(|e| IngestError::Publish(e.into()))?
+                .map_err(|e| IngestError::Publish(e.into()))?;
+                log::info!(
+                    "message accepted: source={}, topic={}, {}",
+                    event.source,
+                    event.topic,
+                    event.format_attributes()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+mod base64_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        bytes: &[u8],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
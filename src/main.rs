@@ -1,15 +1,20 @@
 mod configs;
+mod errors;
+mod event;
 mod logging;
-mod pubsub;
+mod outbox;
 mod services;
+mod signature;
+mod sinks;
 
-use actix_web::{
-    middleware, web, web::ServiceConfig, App, HttpServer,
-};
+use actix_web::{middleware, App, HttpServer};
 
-use crate::{configs::GoogleConfig, configs::IngestorConfig};
+use crate::configs::IngestorConfig;
 
-use crate::services::todoist::TodoistConfig;
+use crate::event::Delivery;
+use crate::outbox::Outbox;
+use crate::services::Registry;
+use crate::sinks::Sinks;
 
 use std::sync::Arc;
 
@@ -21,40 +26,31 @@ async fn main() -> std::io::Result<()> {
         .from_env::<IngestorConfig>()
         .unwrap();
 
-    let google_config = envy::prefixed("GOOGLE_")
-        .from_env::<GoogleConfig>()
-        .unwrap();
-
-    let pubsub = pubsub::new(google_config).await;
+    let sinks =
+        Arc::new(Sinks::from_config(&ingestor_config).await.unwrap());
+
+    let delivery = match &ingestor_config.outbox_dir {
+        None => Delivery::Direct(sinks),
+        Some(dir) => {
+            let outbox = Arc::new(Outbox::open(
+                dir,
+                ingestor_config.outbox_segment_bytes,
+            )?);
+            outbox::spawn_drain(outbox.clone(), sinks);
+            Delivery::Outbox(outbox)
+        }
+    };
+
+    let registry =
+        Registry::from_names(&ingestor_config.sources, delivery)
+            .unwrap();
 
     HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
-            .configure(new_service_config(pubsub.clone()))
+            .configure(|cfg| registry.configure(cfg))
     })
     .bind(ingestor_config.host.to_owned())?
     .run()
     .await
 }
-
-fn new_service_config(
-    pubsub: cloud_pubsub::Client,
-) -> Box<dyn FnOnce(&mut ServiceConfig)> {
-    let todoist_config = envy::prefixed("TODOIST_")
-        .from_env::<TodoistConfig>()
-        .unwrap();
-
-    Box::new(move |cfg: &mut web::ServiceConfig| {
-        cfg.service(
-            web::scope("/todoist")
-                .app_data(web::Data::new(Arc::new(
-                    pubsub.topic(todoist_config.topic.clone()),
-                )))
-                .app_data(web::Data::new(todoist_config.clone()))
-                .route(
-                    "/webhook",
-                    web::post().to(services::todoist::webhook),
-                ),
-        );
-    })
-}
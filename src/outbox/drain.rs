@@ -0,0 +1,279 @@
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{Outbox, Pending};
+use crate::sinks::EventSink;
+
+const BATCH_SIZE: usize = 100;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Starts the background task handing the outbox content to the sink.
+///
+/// The events of a batch are published concurrently, apart from those
+/// sharing an ordering key: each of them is only handed to the sink
+/// once it confirmed the previous one, and none after a failure. The
+/// cursor then moves past the events published without a gap, and the
+/// rest of the batch is read again and retried, with a growing delay.
+/// Events published after a failed one are published again.
+pub fn spawn_drain(outbox: Arc<Outbox>, sink: Arc<dyn EventSink>) {
+    actix_web::rt::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let pending = match outbox.read_pending(BATCH_SIZE) {
+                Ok(pending) => pending,
+                Err(e) => {
+                    log::error!("outbox read failed: {}", e);
+                    actix_web::rt::time::sleep(MAX_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            if pending.is_empty() {
+                outbox.appended().await;
+                continue;
+            }
+
+            let published =
+                publish_batch(sink.as_ref(), &pending, backoff).await;
+            let done = published.iter().take_while(|p| **p).count();
+            if done > 0 {
+                if let Err(e) = outbox.commit(pending[done - 1].end) {
+                    log::error!("outbox commit failed: {}", e);
+                }
+            }
+
+            if done == pending.len() {
+                backoff = INITIAL_BACKOFF;
+            } else {
+                actix_web::rt::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    });
+}
+
+/// Publishes `pending`, one chain of events per ordering key, and
+/// tells which entries are done with. Corrupted entries are skipped.
+async fn publish_batch(
+    sink: &dyn EventSink,
+    pending: &[Pending],
+    retry_in: Duration,
+) -> Vec<bool> {
+    let mut chains: Vec<Vec<usize>> = Vec::new();
+    let mut by_key = HashMap::new();
+    for (index, entry) in pending.iter().enumerate() {
+        let key = entry
+            .event
+            .as_ref()
+            .and_then(|event| event.ordering_key.as_deref());
+        match key {
+            Some(key) => {
+                let chain = *by_key.entry(key).or_insert_with(|| {
+                    chains.push(Vec::new());
+                    chains.len() - 1
+                });
+                chains[chain].push(index);
+            }
+            None => chains.push(vec![index]),
+        }
+    }
+
+    let chains = chains.into_iter().map(|chain| async move {
+        let mut done = Vec::new();
+        for index in chain {
+            let event = match &pending[index].event {
+                Some(event) => event,
+                None => {
+                    done.push(index);
+                    continue;
+                }
+            };
+
+            match sink.publish(event).await {
+                Ok(id) => log::info!(
+                    "message published: source={}, topic={}, id={}, {}",
+                    event.source,
+                    event.topic,
+                    id,
+                    event.format_attributes()
+                ),
+                Err(e) => {
+                    log::warn!(
+                        "outbox publish failed: source={}, topic={}, retry_in={:?}, error={}",
+                        event.source,
+                        event.topic,
+                        retry_in,
+                        e
+                    );
+                    break;
+                }
+            }
+            done.push(index);
+        }
+        done
+    });
+
+    let mut published = vec![false; pending.len()];
+    for index in join_all(chains).await.into_iter().flatten() {
+        published[index] = true;
+    }
+    published
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use futures::future::{self, BoxFuture, FutureExt};
+    use std::sync::Mutex;
+    use tokio::sync::oneshot;
+
+    use super::*;
+    use crate::event::IngestedEvent;
+    use crate::outbox::tests::{event, TempDir};
+
+    /// Records the ordering keys it is given, failing the first event
+    /// with `fail_once`.
+    struct FlakySink {
+        published: Mutex<Vec<String>>,
+        fail_once: Mutex<Option<String>>,
+    }
+
+    impl EventSink for FlakySink {
+        fn publish(
+            &self,
+            event: &IngestedEvent,
+        ) -> BoxFuture<'static, anyhow::Result<String>> {
+            let key = event.ordering_key.clone().unwrap();
+            self.published.lock().unwrap().push(key.clone());
+            let mut fail_once = self.fail_once.lock().unwrap();
+            let result = if fail_once.as_ref() == Some(&key) {
+                *fail_once = None;
+                Err(anyhow!("unavailable"))
+            } else {
+                Ok(key)
+            };
+            future::ready(result).boxed()
+        }
+    }
+
+    /// Records the payloads it is given, keeping each publish pending
+    /// until the test confirms it.
+    #[derive(Default)]
+    struct ManualSink {
+        called: Mutex<Vec<String>>,
+        unconfirmed: Mutex<HashMap<String, oneshot::Sender<()>>>,
+    }
+
+    impl EventSink for ManualSink {
+        fn publish(
+            &self,
+            event: &IngestedEvent,
+        ) -> BoxFuture<'static, anyhow::Result<String>> {
+            let payload =
+                String::from_utf8(event.payload.clone()).unwrap();
+            let (confirm, confirmed) = oneshot::channel();
+            self.called.lock().unwrap().push(payload.clone());
+            self.unconfirmed
+                .lock()
+                .unwrap()
+                .insert(payload.clone(), confirm);
+            async move {
+                confirmed.await?;
+                Ok(payload)
+            }
+            .boxed()
+        }
+    }
+
+    impl ManualSink {
+        fn called(&self) -> Vec<String> {
+            self.called.lock().unwrap().clone()
+        }
+
+        fn confirm(&self, payload: &str) {
+            let confirm =
+                self.unconfirmed.lock().unwrap().remove(payload);
+            confirm.unwrap().send(()).unwrap();
+        }
+    }
+
+    fn keyed(payload: &str, key: &str) -> IngestedEvent {
+        IngestedEvent {
+            ordering_key: Some(key.to_string()),
+            ..event(payload)
+        }
+    }
+
+    async fn eventually(condition: impl Fn() -> bool) {
+        for _ in 0..300 {
+            if condition() {
+                return;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(10))
+                .await;
+        }
+        panic!("condition not met in time");
+    }
+
+    async fn settle() {
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    #[actix_web::test]
+    async fn publishes_keys_concurrently_and_each_key_in_order() {
+        let dir = TempDir::new("drain-keys");
+        let outbox =
+            Arc::new(Outbox::open(&dir.0, 1024 * 1024).unwrap());
+        for (payload, key) in [("a1", "a"), ("a2", "a"), ("b1", "b")]
+        {
+            outbox.append(&keyed(payload, key)).unwrap();
+        }
+        let sink = Arc::new(ManualSink::default());
+
+        spawn_drain(outbox.clone(), sink.clone());
+        eventually(|| sink.called().len() == 2).await;
+        settle().await;
+        assert_eq!(sink.called(), ["a1", "b1"]);
+
+        sink.confirm("b1");
+        settle().await;
+        assert_eq!(sink.called(), ["a1", "b1"]);
+        assert_eq!(outbox.read_pending(10).unwrap().len(), 3);
+
+        sink.confirm("a1");
+        eventually(|| sink.called().len() == 3).await;
+        assert_eq!(sink.called(), ["a1", "b1", "a2"]);
+        sink.confirm("a2");
+        eventually(|| outbox.read_pending(10).unwrap().is_empty())
+            .await;
+    }
+
+    #[actix_web::test]
+    async fn commits_up_to_the_first_failure_and_retries_the_rest() {
+        let dir = TempDir::new("drain");
+        let outbox =
+            Arc::new(Outbox::open(&dir.0, 1024 * 1024).unwrap());
+        for id in ["1", "2", "3"] {
+            outbox.append(&event(id)).unwrap();
+        }
+        let sink = Arc::new(FlakySink {
+            published: Mutex::new(Vec::new()),
+            fail_once: Mutex::new(Some("2".to_string())),
+        });
+
+        spawn_drain(outbox.clone(), sink.clone());
+        eventually(|| sink.published.lock().unwrap().len() >= 5)
+            .await;
+
+        // 3 was published next to the failed 2, and again after it.
+        assert_eq!(
+            *sink.published.lock().unwrap(),
+            ["1", "2", "3", "2", "3"]
+        );
+        eventually(|| outbox.read_pending(10).unwrap().is_empty())
+            .await;
+    }
+}
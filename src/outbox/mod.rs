@@ -0,0 +1,499 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+use crate::event::IngestedEvent;
+
+mod drain;
+
+pub use drain::spawn_drain;
+
+const SEGMENT_PREFIX: &str = "segment-";
+const SEGMENT_SUFFIX: &str = ".jsonl";
+const CURSOR_FILE: &str = "cursor.json";
+
+/// Write-ahead log of the accepted events.
+///
+/// Events are appended, one JSON line each, to numbered segment files
+/// and synced to disk before the webhook is acknowledged. The drainer
+/// publishes them in order and records its progress in a cursor file,
+/// deleting the segments it is done with. Delivery is at-least-once:
+/// an event published right before a crash is published again on
+/// restart.
+pub struct Outbox {
+    dir: PathBuf,
+    segment_bytes: u64,
+    writer: Mutex<SegmentWriter>,
+    reader: Mutex<Option<SegmentReader>>,
+    cursor: Mutex<Cursor>,
+    appended: Notify,
+}
+
+struct SegmentWriter {
+    segment: u64,
+    file: File,
+    len: u64,
+}
+
+/// Segment kept open between reads, so each batch continues where the
+/// previous one stopped.
+struct SegmentReader {
+    segment: u64,
+    offset: u64,
+    file: BufReader<File>,
+}
+
+/// Position right after the last published event.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct Cursor {
+    segment: u64,
+    offset: u64,
+}
+
+/// Event read back from the outbox, `None` when its line is corrupted.
+pub struct Pending {
+    pub event: Option<IngestedEvent>,
+    pub end: Cursor,
+}
+
+impl Outbox {
+    pub fn open(
+        dir: impl AsRef<Path>,
+        segment_bytes: u64,
+    ) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let segments = list_segments(&dir)?;
+
+        let cursor = match fs::read(dir.join(CURSOR_FILE)) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Cursor {
+                segment: segments.first().copied().unwrap_or(0),
+                offset: 0,
+            },
+            Err(e) => return Err(e),
+        };
+        // Never write behind the cursor, even if segments went missing.
+        let last =
+            segments.last().copied().unwrap_or(0).max(cursor.segment);
+
+        let path = segment_path(&dir, last);
+        truncate_partial_line(&path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let len = file.metadata()?.len();
+
+        log::info!(
+            "outbox opened: dir={}, segments={}, cursor={:?}",
+            dir.display(),
+            segments.len(),
+            cursor
+        );
+
+        Ok(Outbox {
+            dir,
+            segment_bytes,
+            writer: Mutex::new(SegmentWriter {
+                segment: last,
+                file,
+                len,
+            }),
+            reader: Mutex::new(None),
+            cursor: Mutex::new(cursor),
+            appended: Notify::new(),
+        })
+    }
+
+    /// Durably appends `event`, rolling to a new segment when the
+    /// current one is full.
+    ///
+    /// Blocks until the event is synced to disk, so async callers run
+    /// it on a blocking thread.
+    pub fn append(&self, event: &IngestedEvent) -> io::Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().unwrap();
+        if writer.len > 0
+            && writer.len + line.len() as u64 > self.segment_bytes
+        {
+            let segment = writer.segment + 1;
+            writer.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(segment_path(&self.dir, segment))?;
+            writer.segment = segment;
+            writer.len = 0;
+        }
+
+        writer.file.write_all(&line)?;
+        writer.file.sync_data()?;
+        writer.len += line.len() as u64;
+        drop(writer);
+
+        self.appended.notify_one();
+        Ok(())
+    }
+
+    /// Reads up to `max` events following the cursor.
+    pub fn read_pending(
+        &self,
+        max: usize,
+    ) -> io::Result<Vec<Pending>> {
+        let mut cursor = *self.cursor.lock().unwrap();
+        loop {
+            // Checked before reading: once the writer moved on, nothing
+            // else can land in the segment under the cursor.
+            let sealed =
+                cursor.segment < self.writer.lock().unwrap().segment;
+
+            let pending = self.read_segment(cursor, max)?;
+            if !pending.is_empty() || !sealed {
+                return Ok(pending);
+            }
+
+            cursor = Cursor {
+                segment: cursor.segment + 1,
+                offset: 0,
+            };
+            self.commit(cursor)?;
+        }
+    }
+
+    /// Records that every event up to `to` is published.
+    pub fn commit(&self, to: Cursor) -> io::Result<()> {
+        let mut cursor = self.cursor.lock().unwrap();
+        for segment in cursor.segment..to.segment {
+            match fs::remove_file(segment_path(&self.dir, segment)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e)
+                }
+                _ => {}
+            }
+        }
+
+        let tmp = self.dir.join(format!("{}.tmp", CURSOR_FILE));
+        fs::write(&tmp, serde_json::to_vec(&to)?)?;
+        fs::rename(&tmp, self.dir.join(CURSOR_FILE))?;
+        *cursor = to;
+        Ok(())
+    }
+
+    /// Waits until an event is appended.
+    pub async fn appended(&self) {
+        self.appended.notified().await
+    }
+
+    fn read_segment(
+        &self,
+        from: Cursor,
+        max: usize,
+    ) -> io::Result<Vec<Pending>> {
+        let mut open = self.reader.lock().unwrap();
+        if open.as_ref().map(|r| r.segment) != Some(from.segment) {
+            let path = segment_path(&self.dir, from.segment);
+            *open = match File::open(path) {
+                Ok(file) => Some(SegmentReader {
+                    segment: from.segment,
+                    offset: 0,
+                    file: BufReader::new(file),
+                }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(Vec::new())
+                }
+                Err(e) => return Err(e),
+            };
+        }
+        let reader = open.as_mut().unwrap();
+        // Events left uncommitted by a failed batch are read again.
+        if reader.offset != from.offset {
+            reader.file.seek(SeekFrom::Start(from.offset))?;
+            reader.offset = from.offset;
+        }
+
+        let mut pending = Vec::new();
+        let mut line = Vec::new();
+        while pending.len() < max {
+            line.clear();
+            if reader.file.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            // A line without its trailing newline is still being
+            // written, it is read again once complete.
+            if !line.ends_with(b"\n") {
+                reader.file.seek(SeekFrom::Start(reader.offset))?;
+                break;
+            }
+            let start = reader.offset;
+            reader.offset += line.len() as u64;
+
+            let event = match serde_json::from_slice(&line) {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    log::error!(
+                        "outbox entry skipped: segment={}, offset={}, error={}",
+                        from.segment,
+                        start,
+                        e
+                    );
+                    None
+                }
+            };
+            pending.push(Pending {
+                event,
+                end: Cursor {
+                    segment: from.segment,
+                    offset: reader.offset,
+                },
+            });
+        }
+        Ok(pending)
+    }
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!(
+        "{}{:020}{}",
+        SEGMENT_PREFIX, segment, SEGMENT_SUFFIX
+    ))
+}
+
+fn list_segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let segment = name
+            .to_str()
+            .and_then(|n| n.strip_prefix(SEGMENT_PREFIX))
+            .and_then(|n| n.strip_suffix(SEGMENT_SUFFIX))
+            .and_then(|n| n.parse().ok());
+        if let Some(segment) = segment {
+            segments.push(segment);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// Drops the incomplete line left by a crash in the middle of a write.
+fn truncate_partial_line(path: &Path) -> io::Result<()> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(())
+        }
+        Err(e) => return Err(e),
+    };
+    let complete = content
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    if complete < content.len() {
+        log::warn!(
+            "outbox segment {} truncated from {} to {} bytes",
+            path.display(),
+            content.len(),
+            complete
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    /// Empty directory, removed when dropped.
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "outbox-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    pub(super) fn event(id: &str) -> IngestedEvent {
+        IngestedEvent {
+            source: "todoist".to_string(),
+            topic: "todoist".to_string(),
+            payload: id.as_bytes().to_vec(),
+            attributes: HashMap::new(),
+            ordering_key: Some(id.to_string()),
+            dedup_id: None,
+            received_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    fn ids(pending: &[Pending]) -> Vec<Option<String>> {
+        pending
+            .iter()
+            .map(|p| {
+                p.event.as_ref().and_then(|e| e.ordering_key.clone())
+            })
+            .collect()
+    }
+
+    fn some(ids: &[&str]) -> Vec<Option<String>> {
+        ids.iter().map(|id| Some(id.to_string())).collect()
+    }
+
+    #[test]
+    fn restores_cursor_on_reopen() {
+        let dir = TempDir::new("cursor");
+        let outbox = Outbox::open(&dir.0, 1024 * 1024).unwrap();
+        for id in ["1", "2", "3"] {
+            outbox.append(&event(id)).unwrap();
+        }
+
+        let pending = outbox.read_pending(10).unwrap();
+        assert_eq!(ids(&pending), some(&["1", "2", "3"]));
+        outbox.commit(pending[1].end).unwrap();
+        drop(outbox);
+
+        let outbox = Outbox::open(&dir.0, 1024 * 1024).unwrap();
+        assert_eq!(
+            ids(&outbox.read_pending(10).unwrap()),
+            some(&["3"])
+        );
+        outbox.append(&event("4")).unwrap();
+        assert_eq!(
+            ids(&outbox.read_pending(10).unwrap()),
+            some(&["3", "4"])
+        );
+    }
+
+    #[test]
+    fn rolls_over_and_reads_across_sealed_segments() {
+        let dir = TempDir::new("segments");
+        // Every event fills a segment.
+        let outbox = Outbox::open(&dir.0, 1).unwrap();
+        for id in ["1", "2", "3"] {
+            outbox.append(&event(id)).unwrap();
+        }
+        assert_eq!(list_segments(&dir.0).unwrap(), vec![0, 1, 2]);
+
+        let mut read = Vec::new();
+        loop {
+            let pending = outbox.read_pending(10).unwrap();
+            if pending.is_empty() {
+                break;
+            }
+            read.extend(ids(&pending));
+            outbox.commit(pending.last().unwrap().end).unwrap();
+        }
+        assert_eq!(read, some(&["1", "2", "3"]));
+        // Published segments are deleted, the one being written stays.
+        assert_eq!(list_segments(&dir.0).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn truncates_partial_line_on_open() {
+        let dir = TempDir::new("partial");
+        let outbox = Outbox::open(&dir.0, 1024 * 1024).unwrap();
+        outbox.append(&event("1")).unwrap();
+        drop(outbox);
+
+        let path = segment_path(&dir.0, 0);
+        let complete = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"source\":\"tod")
+            .unwrap();
+
+        let outbox = Outbox::open(&dir.0, 1024 * 1024).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+        outbox.append(&event("2")).unwrap();
+        assert_eq!(
+            ids(&outbox.read_pending(10).unwrap()),
+            some(&["1", "2"])
+        );
+    }
+
+    #[test]
+    fn skips_corrupted_line() {
+        let dir = TempDir::new("corrupted");
+        let outbox = Outbox::open(&dir.0, 1024 * 1024).unwrap();
+        outbox.append(&event("1")).unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(segment_path(&dir.0, 0))
+            .unwrap()
+            .write_all(b"not json\n")
+            .unwrap();
+        outbox.append(&event("2")).unwrap();
+
+        let pending = outbox.read_pending(10).unwrap();
+        assert_eq!(
+            ids(&pending),
+            vec![Some("1".to_string()), None, Some("2".to_string())]
+        );
+        outbox.commit(pending[2].end).unwrap();
+        assert!(outbox.read_pending(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_batches_from_where_the_last_stopped() {
+        let dir = TempDir::new("batches");
+        let outbox = Outbox::open(&dir.0, 1024 * 1024).unwrap();
+        for id in ["1", "2", "3", "4", "5"] {
+            outbox.append(&event(id)).unwrap();
+        }
+
+        let pending = outbox.read_pending(2).unwrap();
+        assert_eq!(ids(&pending), some(&["1", "2"]));
+        // Uncommitted events are read again.
+        let pending = outbox.read_pending(2).unwrap();
+        assert_eq!(ids(&pending), some(&["1", "2"]));
+        outbox.commit(pending[1].end).unwrap();
+        let pending = outbox.read_pending(2).unwrap();
+        assert_eq!(ids(&pending), some(&["3", "4"]));
+        outbox.commit(pending[1].end).unwrap();
+
+        // A line being written is only read once complete.
+        let mut line = serde_json::to_vec(&event("6")).unwrap();
+        line.push(b'\n');
+        let (head, tail) = line.split_at(line.len() / 2);
+        let mut segment = OpenOptions::new()
+            .append(true)
+            .open(segment_path(&dir.0, 0))
+            .unwrap();
+        segment.write_all(head).unwrap();
+        let pending = outbox.read_pending(2).unwrap();
+        assert_eq!(ids(&pending), some(&["5"]));
+        outbox.commit(pending[0].end).unwrap();
+        assert!(outbox.read_pending(2).unwrap().is_empty());
+        segment.write_all(tail).unwrap();
+        assert_eq!(
+            ids(&outbox.read_pending(2).unwrap()),
+            some(&["6"])
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::{anyhow, Result};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::errors::IngestError;
+use crate::services::WebhookSource;
+use crate::signature;
+
+#[derive(Deserialize, Clone)]
+pub struct GitHubConfig {
+    /// Secret of the webhook, checked against `X-Hub-Signature-256`.
+    pub secret: String,
+    #[serde(default = "default_topic")]
+    pub topic: String,
+}
+
+/// Headers and fields of a delivery, the raw body being published
+/// as is.
+#[derive(Debug)]
+pub struct GitHubEvent {
+    /// `X-GitHub-Event`, e.g. `push` or `pull_request`.
+    name: String,
+    /// `X-GitHub-Delivery`, kept by the redeliveries.
+    delivery: Option<String>,
+    payload: GitHubPayload,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitHubPayload {
+    action: Option<String>,
+    repository: Option<Repository>,
+    sender: Option<Sender>,
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Repository {
+    id: u64,
+    full_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sender {
+    login: String,
+}
+
+/// Webhooks of a GitHub repository, organization or app, sent with
+/// the `application/json` content type.
+pub struct GitHub {
+    config: GitHubConfig,
+}
+
+impl GitHub {
+    pub fn new(config: GitHubConfig) -> Self {
+        GitHub { config }
+    }
+}
+
+impl WebhookSource for GitHub {
+    const NAME: &'static str = "github";
+
+    type Event = GitHubEvent;
+
+    fn from_env() -> Result<Self> {
+        Ok(GitHub::new(
+            envy::prefixed("GITHUB_").from_env::<GitHubConfig>()?,
+        ))
+    }
+
+    fn verify(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<(), IngestError> {
+        authorize_request(body, req, &self.config.secret)
+            .map_err(IngestError::Unauthorized)
+    }
+
+    fn parse(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<GitHubEvent, IngestError> {
+        let name =
+            header(req, "X-GitHub-Event").ok_or_else(|| {
+                IngestError::MalformedPayload(anyhow!(
+                    "Missing X-GitHub-Event header."
+                ))
+            })?;
+        let payload = serde_json::from_slice(body)
+            .map_err(|e| IngestError::MalformedPayload(e.into()))?;
+        Ok(GitHubEvent {
+            name,
+            delivery: header(req, "X-GitHub-Delivery"),
+            payload,
+        })
+    }
+
+    async fn extract_attributes(
+        &self,
+        event: &GitHubEvent,
+    ) -> Result<HashMap<String, String>, IngestError> {
+        let payload = &event.payload;
+        Ok(HashMap::from([
+            ("event_name".to_string(), event.name.clone()),
+            (
+                "action".to_string(),
+                payload.action.clone().unwrap_or_default(),
+            ),
+            (
+                "repository".to_string(),
+                payload
+                    .repository
+                    .as_ref()
+                    .map(|r| r.full_name.clone())
+                    .unwrap_or_default(),
+            ),
+            (
+                "sender".to_string(),
+                payload
+                    .sender
+                    .as_ref()
+                    .map(|s| s.login.clone())
+                    .unwrap_or_default(),
+            ),
+            (
+                "ref".to_string(),
+                payload.git_ref.clone().unwrap_or_default(),
+            ),
+        ]))
+    }
+
+    fn ordering_key(&self, event: &GitHubEvent) -> Option<String> {
+        event.payload.repository.as_ref().map(|r| r.id.to_string())
+    }
+
+    fn topic(&self, _event: &GitHubEvent) -> String {
+        self.config.topic.clone()
+    }
+
+    fn dedup_id(&self, event: &GitHubEvent) -> Option<String> {
+        event.delivery.clone()
+    }
+
+    fn reply(&self, event: &GitHubEvent) -> Option<HttpResponse> {
+        // Sent once when the webhook is created, nothing to publish.
+        (event.name == "ping")
+            .then(|| HttpResponse::Ok().body("pong"))
+    }
+}
+
+fn header(req: &HttpRequest, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+fn authorize_request(
+    body: &[u8],
+    request: &HttpRequest,
+    secret: &str,
+) -> Result<()> {
+    let signature = request
+        .headers()
+        .get("X-Hub-Signature-256")
+        .ok_or(anyhow!("Missing header."))?
+        .to_str()?;
+    let tag = signature
+        .strip_prefix("sha256=")
+        .and_then(|hex| {
+            HEXLOWER_PERMISSIVE.decode(hex.as_bytes()).ok()
+        })
+        .ok_or(anyhow!("Malformed signature."))?;
+
+    if signature::verify_hmac_sha256(secret, body, &tag) {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid Signature."))
+    }
+}
+
+fn default_topic() -> String {
+    "github".to_string()
+}
+
+#[cfg(all(test, feature = "pubsub"))]
+mod tests {
+    use actix_web::test;
+    use data_encoding::HEXLOWER;
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::testing::{self, hmac_sha256};
+
+    const SECRET: &str = "secret";
+
+    fn github() -> GitHub {
+        GitHub::new(GitHubConfig {
+            secret: SECRET.to_string(),
+            topic: "github".to_string(),
+        })
+    }
+
+    fn sign(body: &[u8]) -> String {
+        format!(
+            "sha256={}",
+            HEXLOWER.encode(&hmac_sha256(SECRET, body))
+        )
+    }
+
+    fn delivery(event: &str, body: &[u8]) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/github/webhook")
+            .insert_header(("X-GitHub-Event", event))
+            .insert_header(("X-GitHub-Delivery", "72d3162e-cc78"))
+            .insert_header(("X-Hub-Signature-256", sign(body)))
+            .set_payload(body.to_vec())
+    }
+
+    #[actix_web::test]
+    async fn publishes_signed_push_with_its_attributes() {
+        let (app, pubsub) = testing::app(github()).await;
+
+        let body = json!({
+            "ref": "refs/heads/main",
+            "repository": {"id": 1296269, "full_name": "octocat/Hello-World"},
+            "sender": {"login": "octocat"},
+        })
+        .to_string()
+        .into_bytes();
+        let resp = test::call_service(
+            &app,
+            delivery("push", &body).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 200);
+
+        let published = pubsub.published_to("github");
+        assert_eq!(published.len(), 1);
+        let message = &published[0];
+        assert_eq!(message.data, body);
+        assert_eq!(message.ordering_key.as_deref(), Some("1296269"));
+        assert_eq!(message.attributes["event_name"], "push");
+        assert_eq!(message.attributes["action"], "");
+        assert_eq!(
+            message.attributes["repository"],
+            "octocat/Hello-World"
+        );
+        assert_eq!(message.attributes["sender"], "octocat");
+        assert_eq!(message.attributes["ref"], "refs/heads/main");
+        assert_eq!(message.attributes["dedup_id"], "72d3162e-cc78");
+    }
+
+    #[actix_web::test]
+    async fn answers_ping_without_publishing() {
+        let (app, pubsub) = testing::app(github()).await;
+
+        let body = json!({"zen": "Keep it logically awesome.", "hook_id": 1})
+            .to_string()
+            .into_bytes();
+        let resp = test::call_service(
+            &app,
+            delivery("ping", &body).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 200);
+        assert!(pubsub.published().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_signature() {
+        let (app, pubsub) = testing::app(github()).await;
+
+        let req = delivery("push", b"{}")
+            .insert_header((
+                "X-Hub-Signature-256",
+                sign(b"other body"),
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 401);
+        assert!(pubsub.published().is_empty());
+    }
+}
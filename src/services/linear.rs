@@ -0,0 +1,374 @@
+use actix_web::HttpRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use time::OffsetDateTime;
+
+use crate::errors::IngestError;
+use crate::services::WebhookSource;
+use crate::signature;
+
+#[derive(Deserialize, Clone)]
+pub struct LinearConfig {
+    /// Signing secret of the webhook.
+    pub secret: String,
+    /// Largest gap between `webhookTimestamp` and the reception.
+    #[serde(default = "default_tolerance_secs")]
+    pub tolerance_secs: i64,
+    #[serde(default = "default_topic")]
+    pub topic: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LinearEvent {
+    /// `create`, `update` or `remove`.
+    action: String,
+    /// Kind of entity, e.g. `Issue`, `Comment` or `Project`.
+    #[serde(rename = "type")]
+    entity_type: String,
+    data: Value,
+    /// Sending time, in milliseconds.
+    webhook_timestamp: i64,
+    /// `Linear-Delivery` header, kept by the redeliveries.
+    #[serde(skip)]
+    delivery: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IssueOrCommentData {
+    id: String,
+    /// Set on issues, e.g. `ENG-123`.
+    identifier: Option<String>,
+    team: Option<LinearTeam>,
+    /// Set on comments, along with the issue itself.
+    issue_id: Option<String>,
+    issue: Option<LinearIssue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinearTeam {
+    key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinearIssue {
+    id: String,
+    identifier: Option<String>,
+    team: Option<LinearTeam>,
+}
+
+pub struct ExtractedAttributes {
+    pub team_key: String,
+    pub issue_identifier: String,
+}
+
+impl ExtractedAttributes {
+    fn into_map(
+        self,
+        event: &LinearEvent,
+    ) -> HashMap<String, String> {
+        HashMap::from([
+            (
+                "event_name".to_string(),
+                format!("{}:{}", event.entity_type, event.action),
+            ),
+            ("action".to_string(), event.action.clone()),
+            ("type".to_string(), event.entity_type.clone()),
+            ("team_key".to_string(), self.team_key),
+            ("issue_identifier".to_string(), self.issue_identifier),
+        ])
+    }
+}
+
+pub struct Linear {
+    config: LinearConfig,
+}
+
+impl Linear {
+    pub fn new(config: LinearConfig) -> Self {
+        Linear { config }
+    }
+}
+
+impl WebhookSource for Linear {
+    const NAME: &'static str = "linear";
+
+    // Only the fields used for the attributes, the raw body is
+    //  published as is.
+    type Event = LinearEvent;
+
+    fn from_env() -> Result<Self> {
+        Ok(Linear::new(
+            envy::prefixed("LINEAR_").from_env::<LinearConfig>()?,
+        ))
+    }
+
+    fn verify(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<(), IngestError> {
+        authorize_request(
+            body,
+            req,
+            &self.config.secret,
+            self.config.tolerance_secs,
+            OffsetDateTime::now_utc().unix_timestamp(),
+        )
+        .map_err(IngestError::Unauthorized)
+    }
+
+    fn parse(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<LinearEvent, IngestError> {
+        let mut event: LinearEvent = serde_json::from_slice(body)
+            .map_err(|e| IngestError::MalformedPayload(e.into()))?;
+        event.delivery = req
+            .headers()
+            .get("Linear-Delivery")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok(event)
+    }
+
+    async fn extract_attributes(
+        &self,
+        event: &LinearEvent,
+    ) -> Result<HashMap<String, String>, IngestError> {
+        let attr = extract_issue_attributes(event)
+            .map_err(IngestError::MalformedPayload)?;
+        Ok(attr.into_map(event))
+    }
+
+    fn ordering_key(&self, event: &LinearEvent) -> Option<String> {
+        issue_or_comment(event)
+            .ok()
+            .flatten()
+            .and_then(|data| issue_id(event, data))
+    }
+
+    fn topic(&self, _event: &LinearEvent) -> String {
+        self.config.topic.clone()
+    }
+
+    fn dedup_id(&self, event: &LinearEvent) -> Option<String> {
+        event.delivery.clone()
+    }
+}
+
+/// Data of the issue and comment events, none for the other types.
+fn issue_or_comment(
+    event: &LinearEvent,
+) -> Result<Option<IssueOrCommentData>> {
+    match event.entity_type.as_str() {
+        "Issue" | "Comment" => {
+            serde_json::from_value(event.data.clone())
+                .context("Failed to extract issue or comment event")
+                .map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+fn issue_id(
+    event: &LinearEvent,
+    data: IssueOrCommentData,
+) -> Option<String> {
+    if event.entity_type == "Issue" {
+        Some(data.id)
+    } else {
+        data.issue_id.or(data.issue.map(|issue| issue.id))
+    }
+}
+
+fn extract_issue_attributes(
+    event: &LinearEvent,
+) -> Result<ExtractedAttributes> {
+    let data = match issue_or_comment(event)? {
+        Some(data) => data,
+        None => {
+            return Ok(ExtractedAttributes {
+                team_key: "".to_string(),
+                issue_identifier: "".to_string(),
+            })
+        }
+    };
+
+    let (identifier, team) = match data.issue {
+        Some(issue) => (issue.identifier, issue.team),
+        None => (data.identifier, data.team),
+    };
+    let issue_identifier = identifier.unwrap_or_default();
+    // The identifier starts with the team key, e.g. `ENG-123`.
+    let team_key = match team {
+        Some(team) => team.key,
+        None => issue_identifier
+            .rsplit_once('-')
+            .map(|(key, _)| key.to_string())
+            .unwrap_or_default(),
+    };
+
+    Ok(ExtractedAttributes {
+        team_key,
+        issue_identifier,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Timestamp {
+    webhook_timestamp: i64,
+}
+
+fn authorize_request(
+    body: &[u8],
+    request: &HttpRequest,
+    secret: &str,
+    tolerance_secs: i64,
+    now: i64,
+) -> Result<()> {
+    let signature = request
+        .headers()
+        .get("Linear-Signature")
+        .ok_or(anyhow!("Missing header."))?
+        .to_str()?;
+    let tag = HEXLOWER_PERMISSIVE
+        .decode(signature.as_bytes())
+        .map_err(|_| anyhow!("Malformed signature."))?;
+
+    if !signature::verify_hmac_sha256(secret, body, &tag) {
+        return Err(anyhow!("Invalid Signature."));
+    }
+
+    // Part of the signed body, so it can be trusted once verified.
+    let sent_at = serde_json::from_slice::<Timestamp>(body)
+        .context("Missing webhookTimestamp.")?
+        .webhook_timestamp
+        / 1000;
+    if (now - sent_at).abs() > tolerance_secs {
+        return Err(anyhow!(
+            "webhookTimestamp {} outside the tolerance.",
+            sent_at
+        ));
+    }
+    Ok(())
+}
+
+fn default_tolerance_secs() -> i64 {
+    60
+}
+
+fn default_topic() -> String {
+    "linear".to_string()
+}
+
+#[cfg(all(test, feature = "pubsub"))]
+mod tests {
+    use actix_web::test;
+    use data_encoding::HEXLOWER;
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::testing::{self, hmac_sha256};
+
+    const SECRET: &str = "secret";
+
+    fn linear() -> Linear {
+        Linear::new(LinearConfig {
+            secret: SECRET.to_string(),
+            tolerance_secs: 60,
+            topic: "linear".to_string(),
+        })
+    }
+
+    fn now_ms() -> i64 {
+        OffsetDateTime::now_utc().unix_timestamp() * 1000
+    }
+
+    fn comment_created(webhook_timestamp: i64) -> Vec<u8> {
+        json!({
+            "action": "create",
+            "type": "Comment",
+            "createdAt": "2024-05-02T10:00:00.000Z",
+            "data": {
+                "id": "c1",
+                "body": "Looks good",
+                "issueId": "i1",
+                "issue": {"id": "i1", "identifier": "ENG-42", "title": "Fix"},
+            },
+            "url": "https://linear.app/acme/issue/ENG-42#comment-c1",
+            "webhookTimestamp": webhook_timestamp,
+            "webhookId": "w1",
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn sign(body: &[u8]) -> String {
+        HEXLOWER.encode(&hmac_sha256(SECRET, body))
+    }
+
+    #[actix_web::test]
+    async fn publishes_signed_webhook_with_its_attributes() {
+        let (app, pubsub) = testing::app(linear()).await;
+
+        let body = comment_created(now_ms());
+        let req = test::TestRequest::post()
+            .uri("/linear/webhook")
+            .insert_header(("Linear-Signature", sign(&body)))
+            .insert_header((
+                "Linear-Delivery",
+                "234d1a4e-b617-4388-90fe-adc3633d6b72",
+            ))
+            .set_payload(body.clone())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+
+        let published = pubsub.published_to("linear");
+        assert_eq!(published.len(), 1);
+        let message = &published[0];
+        assert_eq!(message.data, body);
+        assert_eq!(message.ordering_key.as_deref(), Some("i1"));
+        assert_eq!(
+            message.attributes["event_name"],
+            "Comment:create"
+        );
+        assert_eq!(message.attributes["action"], "create");
+        assert_eq!(message.attributes["type"], "Comment");
+        assert_eq!(message.attributes["team_key"], "ENG");
+        assert_eq!(message.attributes["issue_identifier"], "ENG-42");
+        assert_eq!(
+            message.attributes["dedup_id"],
+            "234d1a4e-b617-4388-90fe-adc3633d6b72"
+        );
+    }
+
+    #[actix_web::test]
+    async fn rejects_stale_and_unsigned_webhooks() {
+        let (app, pubsub) = testing::app(linear()).await;
+
+        let stale = comment_created(now_ms() - 120_000);
+        let fresh = comment_created(now_ms());
+        for (signature, body) in
+            [(sign(&stale), stale), (sign(b"other body"), fresh)]
+        {
+            let req = test::TestRequest::post()
+                .uri("/linear/webhook")
+                .insert_header(("Linear-Signature", signature))
+                .set_payload(body)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 401);
+        }
+        assert!(pubsub.published().is_empty());
+    }
+}
@@ -1 +1,199 @@
+pub mod github;
+pub mod linear;
+pub mod slack;
+pub mod stripe;
+#[cfg(all(test, feature = "pubsub"))]
+mod testing;
 pub mod todoist;
+
+use actix_web::web::{self, Bytes, ServiceConfig};
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::collections::HashMap;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+use crate::errors::IngestError;
+use crate::event::{Delivery, IngestedEvent};
+use crate::services::github::GitHub;
+use crate::services::linear::Linear;
+use crate::services::slack::Slack;
+use crate::services::stripe::Stripe;
+use crate::services::todoist::Todoist;
+
+/// A SaaS integration pushing its events through a webhook.
+///
+/// Each step of the ingestion is a method so the generic [`webhook`]
+/// handler can drive any source the same way: verify, parse, extract
+/// the attributes, then deliver the raw payload to the chosen topic.
+pub trait WebhookSource: Sized + 'static {
+    /// Name of the source, also used as its route prefix.
+    const NAME: &'static str;
+
+    /// Answers the webhook before publishing the event, for senders
+    /// giving up after a few seconds. A failed publish is then only
+    /// logged, the sender having been told it succeeded. Events going
+    /// through the outbox are still appended before answering.
+    const DEFER_DELIVERY: bool = false;
+
+    type Event: std::fmt::Debug;
+
+    fn from_env() -> Result<Self>;
+
+    fn verify(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<(), IngestError>;
+
+    fn parse(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<Self::Event, IngestError>;
+
+    async fn extract_attributes(
+        &self,
+        event: &Self::Event,
+    ) -> Result<HashMap<String, String>, IngestError>;
+
+    fn ordering_key(&self, event: &Self::Event) -> Option<String>;
+
+    fn topic(&self, event: &Self::Event) -> String;
+
+    /// Id shared by every delivery of the same event, so the sinks
+    /// able to drop duplicates do so when the sender retries.
+    fn dedup_id(&self, _event: &Self::Event) -> Option<String> {
+        None
+    }
+
+    /// Answer of a handshake event, e.g. a ping, acknowledged without
+    /// being published.
+    fn reply(&self, _event: &Self::Event) -> Option<HttpResponse> {
+        None
+    }
+}
+
+type Mount = Arc<dyn Fn(&mut ServiceConfig) + Send + Sync>;
+
+/// Set of enabled sources, each mounted under `/{source}/webhook`.
+#[derive(Clone)]
+pub struct Registry {
+    delivery: Delivery,
+    mounts: Vec<Mount>,
+}
+
+impl Registry {
+    pub fn new(delivery: Delivery) -> Self {
+        Registry {
+            delivery,
+            mounts: Vec::new(),
+        }
+    }
+
+    /// Builds a registry from the names listed in the configuration.
+    pub fn from_names(
+        names: &[String],
+        delivery: Delivery,
+    ) -> Result<Self> {
+        let mut registry = Registry::new(delivery);
+        for name in names {
+            match name.as_str() {
+                Todoist::NAME => {
+                    registry.register(Todoist::from_env()?)
+                }
+                GitHub::NAME => {
+                    registry.register(GitHub::from_env()?)
+                }
+                Stripe::NAME => {
+                    registry.register(Stripe::from_env()?)
+                }
+                Slack::NAME => registry.register(Slack::from_env()?),
+                Linear::NAME => {
+                    registry.register(Linear::from_env()?)
+                }
+                other => {
+                    return Err(anyhow!(
+                        "Unknown webhook source: {}",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn register<S: WebhookSource + Send + Sync>(
+        &mut self,
+        source: S,
+    ) {
+        let source = web::Data::new(source);
+        self.mounts.push(Arc::new(move |cfg: &mut ServiceConfig| {
+            cfg.service(
+                web::scope(&format!("/{}", S::NAME))
+                    .app_data(source.clone())
+                    .route("/webhook", web::post().to(webhook::<S>)),
+            );
+        }));
+    }
+
+    pub fn configure(&self, cfg: &mut ServiceConfig) {
+        cfg.app_data(web::Data::new(self.delivery.clone()));
+        for mount in &self.mounts {
+            mount(cfg);
+        }
+    }
+}
+
+pub async fn webhook<S: WebhookSource>(
+    req: HttpRequest,
+    body: Bytes,
+    source: web::Data<S>,
+    delivery: web::Data<Delivery>,
+) -> Result<HttpResponse, IngestError> {
+    ingest(&req, &body, source.get_ref(), &delivery)
+        .await
+        .inspect_err(|e| e.log(S::NAME))
+}
+
+async fn ingest<S: WebhookSource>(
+    req: &HttpRequest,
+    body: &Bytes,
+    source: &S,
+    delivery: &Delivery,
+) -> Result<HttpResponse, IngestError> {
+    source.verify(req, body)?;
+
+    let event = source.parse(req, body)?;
+    debug!("{} event: {:?}", S::NAME, event);
+    if let Some(response) = source.reply(&event) {
+        return Ok(response);
+    }
+
+    let attributes = source.extract_attributes(&event).await?;
+
+    let event = IngestedEvent {
+        source: S::NAME.to_string(),
+        topic: source.topic(&event),
+        payload: body.to_vec(),
+        attributes,
+        ordering_key: source.ordering_key(&event),
+        dedup_id: source.dedup_id(&event),
+        received_at: OffsetDateTime::now_utc(),
+    };
+    // The outbox append is local and quick, it still happens before
+    // answering so the event is on disk once acknowledged.
+    if S::DEFER_DELIVERY && matches!(delivery, Delivery::Direct(_)) {
+        let delivery = delivery.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = delivery.deliver(&event).await {
+                e.log(S::NAME);
+            }
+        });
+    } else {
+        delivery.deliver(&event).await?;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
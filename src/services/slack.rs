@@ -0,0 +1,420 @@
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::{anyhow, Result};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+use crate::errors::IngestError;
+use crate::services::WebhookSource;
+use crate::signature;
+
+#[derive(Deserialize, Clone)]
+pub struct SlackConfig {
+    pub signing_secret: String,
+    /// Largest gap between the request timestamp and the reception.
+    #[serde(default = "default_tolerance_secs")]
+    pub tolerance_secs: i64,
+    #[serde(default = "default_topic")]
+    pub topic: String,
+}
+
+/// Request of the Events API.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SlackEvent {
+    /// Sent when the request URL is set, answered with its challenge.
+    UrlVerification {
+        challenge: String,
+    },
+    EventCallback(Callback),
+    /// Other notices, e.g. `app_rate_limited`, nothing to publish.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Callback {
+    team_id: Option<String>,
+    event_id: String,
+    event: InnerEvent,
+}
+
+#[derive(Deserialize, Debug)]
+struct InnerEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    /// An id, or an object with one, e.g. for `user_change`.
+    user: Option<Value>,
+    /// An id, or an object with one, e.g. for `channel_created`.
+    channel: Option<Value>,
+}
+
+/// Events API of a Slack app.
+///
+/// Slack gives up on a request after 3 seconds, so the webhook answers
+/// before the event is delivered.
+pub struct Slack {
+    config: SlackConfig,
+}
+
+impl Slack {
+    pub fn new(config: SlackConfig) -> Self {
+        Slack { config }
+    }
+}
+
+impl WebhookSource for Slack {
+    const NAME: &'static str = "slack";
+    const DEFER_DELIVERY: bool = true;
+
+    type Event = SlackEvent;
+
+    fn from_env() -> Result<Self> {
+        Ok(Slack::new(
+            envy::prefixed("SLACK_").from_env::<SlackConfig>()?,
+        ))
+    }
+
+    fn verify(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<(), IngestError> {
+        authorize_request(
+            body,
+            req,
+            &self.config.signing_secret,
+            self.config.tolerance_secs,
+            OffsetDateTime::now_utc().unix_timestamp(),
+        )
+        .map_err(IngestError::Unauthorized)
+    }
+
+    fn parse(
+        &self,
+        _req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<SlackEvent, IngestError> {
+        serde_json::from_slice(body)
+            .map_err(|e| IngestError::MalformedPayload(e.into()))
+    }
+
+    async fn extract_attributes(
+        &self,
+        event: &SlackEvent,
+    ) -> Result<HashMap<String, String>, IngestError> {
+        let callback = callback(event)?;
+        Ok(HashMap::from([
+            (
+                "team_id".to_string(),
+                callback.team_id.clone().unwrap_or_default(),
+            ),
+            (
+                "channel".to_string(),
+                id_of(&callback.event.channel).unwrap_or_default(),
+            ),
+            (
+                "event_name".to_string(),
+                callback.event.event_type.clone(),
+            ),
+            (
+                "user".to_string(),
+                id_of(&callback.event.user).unwrap_or_default(),
+            ),
+        ]))
+    }
+
+    fn ordering_key(&self, event: &SlackEvent) -> Option<String> {
+        callback(event)
+            .ok()
+            .and_then(|callback| id_of(&callback.event.channel))
+    }
+
+    fn topic(&self, _event: &SlackEvent) -> String {
+        self.config.topic.clone()
+    }
+
+    fn dedup_id(&self, event: &SlackEvent) -> Option<String> {
+        callback(event).ok().map(|c| c.event_id.clone())
+    }
+
+    fn reply(&self, event: &SlackEvent) -> Option<HttpResponse> {
+        match event {
+            SlackEvent::UrlVerification { challenge } => Some(
+                HttpResponse::Ok()
+                    .content_type("text/plain")
+                    .body(challenge.clone()),
+            ),
+            SlackEvent::EventCallback(_) => None,
+            SlackEvent::Other => Some(HttpResponse::Ok().finish()),
+        }
+    }
+}
+
+/// Every event left once the handshakes are answered.
+fn callback(event: &SlackEvent) -> Result<&Callback, IngestError> {
+    match event {
+        SlackEvent::EventCallback(callback) => Ok(callback),
+        _ => Err(IngestError::MalformedPayload(anyhow!(
+            "Not an event callback."
+        ))),
+    }
+}
+
+fn id_of(value: &Option<Value>) -> Option<String> {
+    match value {
+        Some(Value::String(id)) => Some(id.clone()),
+        Some(object) => object
+            .get("id")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        None => None,
+    }
+}
+
+/// Checks `X-Slack-Signature`, `v0=` followed by the hex HMAC of
+/// `v0:{timestamp}:{body}`, and that `X-Slack-Request-Timestamp` is
+/// within the tolerance of `now`.
+fn authorize_request(
+    body: &[u8],
+    request: &HttpRequest,
+    signing_secret: &str,
+    tolerance_secs: i64,
+    now: i64,
+) -> Result<()> {
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .ok_or(anyhow!("Missing header {}.", name))?
+            .to_str()
+            .map_err(anyhow::Error::from)
+    };
+    let timestamp = header("X-Slack-Request-Timestamp")?;
+    let tag = header("X-Slack-Signature")?
+        .strip_prefix("v0=")
+        .and_then(|hex| {
+            HEXLOWER_PERMISSIVE.decode(hex.as_bytes()).ok()
+        })
+        .ok_or(anyhow!("Malformed signature."))?;
+
+    let mut signed = format!("v0:{}:", timestamp).into_bytes();
+    signed.extend_from_slice(body);
+    if !signature::verify_hmac_sha256(signing_secret, &signed, &tag) {
+        return Err(anyhow!("Invalid Signature."));
+    }
+    let timestamp: i64 = timestamp.parse()?;
+    if (now - timestamp).abs() > tolerance_secs {
+        return Err(anyhow!(
+            "Request timestamp {} outside the tolerance.",
+            timestamp
+        ));
+    }
+    Ok(())
+}
+
+fn default_tolerance_secs() -> i64 {
+    5 * 60
+}
+
+fn default_topic() -> String {
+    "slack".to_string()
+}
+
+#[cfg(all(test, feature = "pubsub"))]
+mod tests {
+    use actix_web::rt::time::{sleep, timeout};
+    use actix_web::test;
+    use data_encoding::HEXLOWER;
+    use futures::future::{BoxFuture, FutureExt};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::Semaphore;
+
+    use super::*;
+    use crate::event::{Delivery, IngestedEvent};
+    use crate::outbox::tests::TempDir;
+    use crate::outbox::Outbox;
+    use crate::services::testing::{self, hmac_sha256};
+    use crate::sinks::EventSink;
+
+    const SIGNING_SECRET: &str = "secret";
+
+    fn slack() -> Slack {
+        Slack::new(SlackConfig {
+            signing_secret: SIGNING_SECRET.to_string(),
+            tolerance_secs: 300,
+            topic: "slack".to_string(),
+        })
+    }
+
+    fn request(timestamp: i64, body: &[u8]) -> test::TestRequest {
+        let mut signed = format!("v0:{}:", timestamp).into_bytes();
+        signed.extend_from_slice(body);
+        let signature = format!(
+            "v0={}",
+            HEXLOWER.encode(&hmac_sha256(SIGNING_SECRET, &signed))
+        );
+        test::TestRequest::post()
+            .uri("/slack/webhook")
+            .insert_header(("X-Slack-Request-Timestamp", timestamp))
+            .insert_header(("X-Slack-Signature", signature))
+            .set_payload(body.to_vec())
+    }
+
+    fn now() -> i64 {
+        OffsetDateTime::now_utc().unix_timestamp()
+    }
+
+    #[actix_web::test]
+    async fn answers_url_verification_challenge() {
+        let (app, pubsub) = testing::app(slack()).await;
+
+        let body = json!({
+            "type": "url_verification",
+            "token": "legacy",
+            "challenge": "3eZbrw1aBm2rZgRNFdxV2595E9CY3gmdALWMmHkvFXO7tYXAYM8P",
+        })
+        .to_string()
+        .into_bytes();
+        let resp = test::call_service(
+            &app,
+            request(now(), &body).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            test::read_body(resp).await,
+            "3eZbrw1aBm2rZgRNFdxV2595E9CY3gmdALWMmHkvFXO7tYXAYM8P"
+        );
+        assert!(pubsub.published().is_empty());
+    }
+
+    /// Keeps the events it is given until the test adds a permit to
+    /// the gate.
+    struct GatedSink {
+        gate: Arc<Semaphore>,
+        published: Arc<Mutex<Vec<IngestedEvent>>>,
+    }
+
+    impl EventSink for GatedSink {
+        fn publish(
+            &self,
+            event: &IngestedEvent,
+        ) -> BoxFuture<'static, anyhow::Result<String>> {
+            let gate = self.gate.clone();
+            let published = self.published.clone();
+            let event = event.clone();
+            async move {
+                gate.acquire().await?.forget();
+                published.lock().unwrap().push(event);
+                Ok("1".to_string())
+            }
+            .boxed()
+        }
+    }
+
+    fn message_event() -> Vec<u8> {
+        json!({
+            "type": "event_callback",
+            "team_id": "T1",
+            "event_id": "Ev1",
+            "event": {
+                "type": "message",
+                "user": "U1",
+                "channel": "C1",
+                "text": "hello",
+            },
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    #[actix_web::test]
+    async fn answers_before_publishing() {
+        let sink = Arc::new(GatedSink {
+            gate: Arc::new(Semaphore::new(0)),
+            published: Arc::new(Mutex::new(Vec::new())),
+        });
+        let app = testing::app_with(
+            slack(),
+            Delivery::Direct(sink.clone()),
+        )
+        .await;
+
+        let body = message_event();
+        let resp = timeout(
+            Duration::from_secs(1),
+            test::call_service(
+                &app,
+                request(now(), &body).to_request(),
+            ),
+        )
+        .await
+        .expect("answered only once published");
+        assert_eq!(resp.status(), 200);
+        assert!(sink.published.lock().unwrap().is_empty());
+
+        sink.gate.add_permits(1);
+        for _ in 0..100 {
+            if !sink.published.lock().unwrap().is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        let published = sink.published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        let event = &published[0];
+        assert_eq!(event.payload, body);
+        assert_eq!(event.ordering_key.as_deref(), Some("C1"));
+        assert_eq!(event.attributes["team_id"], "T1");
+        assert_eq!(event.attributes["channel"], "C1");
+        assert_eq!(event.attributes["event_name"], "message");
+        assert_eq!(event.attributes["user"], "U1");
+        assert_eq!(event.dedup_id.as_deref(), Some("Ev1"));
+    }
+
+    #[actix_web::test]
+    async fn appends_to_outbox_before_answering() {
+        let dir = TempDir::new("slack");
+        let outbox =
+            Arc::new(Outbox::open(&dir.0, 1024 * 1024).unwrap());
+        let app = testing::app_with(
+            slack(),
+            Delivery::Outbox(outbox.clone()),
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            request(now(), &message_event()).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 200);
+
+        let pending = outbox.read_pending(10).unwrap();
+        assert_eq!(pending.len(), 1);
+        let event = pending[0].event.as_ref().unwrap();
+        assert_eq!(event.dedup_id.as_deref(), Some("Ev1"));
+    }
+
+    #[actix_web::test]
+    async fn rejects_stale_timestamp() {
+        let (app, pubsub) = testing::app(slack()).await;
+
+        let body =
+            json!({"type": "url_verification", "challenge": "x"})
+                .to_string()
+                .into_bytes();
+        let resp = test::call_service(
+            &app,
+            request(now() - 600, &body).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+        assert!(pubsub.published().is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+use actix_web::HttpRequest;
+use anyhow::{anyhow, Result};
+use data_encoding::HEXLOWER_PERMISSIVE;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+use crate::errors::IngestError;
+use crate::services::WebhookSource;
+use crate::signature;
+
+#[derive(Deserialize, Clone)]
+pub struct StripeConfig {
+    /// Signing secrets of the endpoint, e.g. `whsec_new,whsec_old`
+    /// while a secret is rolled.
+    pub secrets: Vec<String>,
+    /// Largest gap between the signature time and the reception.
+    #[serde(default = "default_tolerance_secs")]
+    pub tolerance_secs: i64,
+    #[serde(default = "default_topic")]
+    pub topic: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StripeEvent {
+    id: String,
+    #[serde(rename = "type")]
+    event_type: String,
+    livemode: bool,
+    /// Connected account of the event, Connect webhooks only.
+    account: Option<String>,
+    data: StripeData,
+}
+
+#[derive(Deserialize, Debug)]
+struct StripeData {
+    object: Value,
+}
+
+/// Webhooks of a Stripe endpoint.
+pub struct Stripe {
+    config: StripeConfig,
+}
+
+impl Stripe {
+    pub fn new(config: StripeConfig) -> Self {
+        Stripe { config }
+    }
+}
+
+impl WebhookSource for Stripe {
+    const NAME: &'static str = "stripe";
+
+    type Event = StripeEvent;
+
+    fn from_env() -> Result<Self> {
+        Ok(Stripe::new(
+            envy::prefixed("STRIPE_").from_env::<StripeConfig>()?,
+        ))
+    }
+
+    fn verify(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<(), IngestError> {
+        authorize_request(
+            body,
+            req,
+            &self.config.secrets,
+            self.config.tolerance_secs,
+            OffsetDateTime::now_utc().unix_timestamp(),
+        )
+        .map_err(IngestError::Unauthorized)
+    }
+
+    fn parse(
+        &self,
+        _req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<StripeEvent, IngestError> {
+        serde_json::from_slice(body)
+            .map_err(|e| IngestError::MalformedPayload(e.into()))
+    }
+
+    async fn extract_attributes(
+        &self,
+        event: &StripeEvent,
+    ) -> Result<HashMap<String, String>, IngestError> {
+        let object_id = event
+            .data
+            .object
+            .get("id")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        Ok(HashMap::from([
+            ("event_name".to_string(), event.event_type.clone()),
+            ("livemode".to_string(), event.livemode.to_string()),
+            (
+                "account".to_string(),
+                event.account.clone().unwrap_or_default(),
+            ),
+            ("object_id".to_string(), object_id.to_string()),
+        ]))
+    }
+
+    fn ordering_key(&self, event: &StripeEvent) -> Option<String> {
+        let object = &event.data.object;
+        if object.get("object").and_then(Value::as_str)
+            == Some("customer")
+        {
+            return object
+                .get("id")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+        }
+        // Expanded customers are objects, their id is kept.
+        match object.get("customer") {
+            Some(Value::String(id)) => Some(id.clone()),
+            Some(customer) => customer
+                .get("id")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            None => None,
+        }
+    }
+
+    fn topic(&self, _event: &StripeEvent) -> String {
+        self.config.topic.clone()
+    }
+
+    fn dedup_id(&self, event: &StripeEvent) -> Option<String> {
+        Some(event.id.clone())
+    }
+}
+
+/// Checks `Stripe-Signature`, e.g. `t=1492774577,v1=5257a869...`: one
+/// of the `v1` HMACs of `{t}.{body}` must match one of the secrets, and
+/// `t` must be within the tolerance of `now`.
+fn authorize_request(
+    body: &[u8],
+    request: &HttpRequest,
+    secrets: &[String],
+    tolerance_secs: i64,
+    now: i64,
+) -> Result<()> {
+    let header = request
+        .headers()
+        .get("Stripe-Signature")
+        .ok_or(anyhow!("Missing header."))?
+        .to_str()?;
+
+    let mut timestamp = None;
+    let mut tags = Vec::new();
+    for (key, value) in
+        header.split(',').filter_map(|part| part.split_once('='))
+    {
+        match key.trim() {
+            "t" => timestamp = value.parse::<i64>().ok(),
+            "v1" => tags.extend(
+                HEXLOWER_PERMISSIVE.decode(value.as_bytes()).ok(),
+            ),
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or(anyhow!("Missing timestamp."))?;
+
+    let mut signed = format!("{}.", timestamp).into_bytes();
+    signed.extend_from_slice(body);
+    let valid = secrets.iter().any(|secret| {
+        tags.iter().any(|tag| {
+            signature::verify_hmac_sha256(secret, &signed, tag)
+        })
+    });
+    if !valid {
+        return Err(anyhow!("Invalid Signature."));
+    }
+    // Checked once signed, so the timestamp can be trusted.
+    if (now - timestamp).abs() > tolerance_secs {
+        return Err(anyhow!(
+            "Signature timestamp {} outside the tolerance.",
+            timestamp
+        ));
+    }
+    Ok(())
+}
+
+fn default_tolerance_secs() -> i64 {
+    5 * 60
+}
+
+fn default_topic() -> String {
+    "stripe".to_string()
+}
+
+#[cfg(all(test, feature = "pubsub"))]
+mod tests {
+    use actix_web::test;
+    use data_encoding::HEXLOWER;
+    use serde_json::json;
+
+    use super::*;
+    use crate::services::testing::{self, hmac_sha256};
+
+    fn stripe() -> Stripe {
+        Stripe::new(StripeConfig {
+            secrets: vec![
+                "whsec_new".to_string(),
+                "whsec_old".to_string(),
+            ],
+            tolerance_secs: 300,
+            topic: "stripe".to_string(),
+        })
+    }
+
+    fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+        let mut signed = format!("{}.", timestamp).into_bytes();
+        signed.extend_from_slice(body);
+        format!(
+            "t={},v1={}",
+            timestamp,
+            HEXLOWER.encode(&hmac_sha256(secret, &signed))
+        )
+    }
+
+    fn invoice_paid() -> Vec<u8> {
+        json!({
+            "id": "evt_1",
+            "type": "invoice.paid",
+            "livemode": false,
+            "account": "acct_1",
+            "data": {
+                "object": {
+                    "id": "in_1",
+                    "object": "invoice",
+                    "customer": "cus_1",
+                },
+            },
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn now() -> i64 {
+        OffsetDateTime::now_utc().unix_timestamp()
+    }
+
+    #[actix_web::test]
+    async fn publishes_event_signed_with_a_rotated_secret() {
+        let (app, pubsub) = testing::app(stripe()).await;
+
+        let body = invoice_paid();
+        let req = test::TestRequest::post()
+            .uri("/stripe/webhook")
+            .insert_header((
+                "Stripe-Signature",
+                sign("whsec_old", now(), &body),
+            ))
+            .set_payload(body.clone())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+
+        let published = pubsub.published_to("stripe");
+        assert_eq!(published.len(), 1);
+        let message = &published[0];
+        assert_eq!(message.data, body);
+        assert_eq!(message.ordering_key.as_deref(), Some("cus_1"));
+        assert_eq!(message.attributes["event_name"], "invoice.paid");
+        assert_eq!(message.attributes["livemode"], "false");
+        assert_eq!(message.attributes["account"], "acct_1");
+        assert_eq!(message.attributes["object_id"], "in_1");
+        assert_eq!(message.attributes["dedup_id"], "evt_1");
+    }
+
+    #[actix_web::test]
+    async fn rejects_replayed_and_unsigned_events() {
+        let (app, pubsub) = testing::app(stripe()).await;
+
+        let body = invoice_paid();
+        for signature in [
+            sign("whsec_new", now() - 600, &body),
+            sign("whsec_unknown", now(), &body),
+            format!("t={}", now()),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/stripe/webhook")
+                .insert_header(("Stripe-Signature", signature))
+                .set_payload(body.clone())
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), 401);
+        }
+        assert!(pubsub.published().is_empty());
+    }
+}
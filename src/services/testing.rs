@@ -0,0 +1,46 @@
+//! Setup shared by the tests of the webhook sources.
+
+use actix_http::Request;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, App, Error};
+use cloud_pubsub::MemoryPubSub;
+use ring::hmac;
+use std::sync::Arc;
+
+use crate::event::Delivery;
+use crate::services::{Registry, WebhookSource};
+use crate::sinks::pubsub::PubSubSink;
+
+/// App serving the webhook of `source`, its events published right
+/// away to the returned in-memory Pub/Sub.
+pub(crate) async fn app<S: WebhookSource + Send + Sync>(
+    source: S,
+) -> (
+    impl Service<Request, Response = ServiceResponse, Error = Error>,
+    MemoryPubSub,
+) {
+    let pubsub = MemoryPubSub::new();
+    let sink = Arc::new(PubSubSink::new(Arc::new(pubsub.clone())));
+    (app_with(source, Delivery::Direct(sink)).await, pubsub)
+}
+
+/// App serving the webhook of `source`, its events handed to
+/// `delivery`.
+pub(crate) async fn app_with<S: WebhookSource + Send + Sync>(
+    source: S,
+    delivery: Delivery,
+) -> impl Service<Request, Response = ServiceResponse, Error = Error>
+{
+    let mut registry = Registry::new(delivery);
+    registry.register(source);
+    test::init_service(
+        App::new().configure(|cfg| registry.configure(cfg)),
+    )
+    .await
+}
+
+/// Raw HMAC-SHA256 tag of `message`, encoded as each source expects.
+pub(crate) fn hmac_sha256(secret: &str, message: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::sign(&key, message).as_ref().to_vec()
+}
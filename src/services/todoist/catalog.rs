@@ -0,0 +1,632 @@
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use reqwest::header::AUTHORIZATION;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use super::{TodoistEvent, TodoistProject, TodoistSection};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before retrying a failed reload, or the lookup of a project
+/// or section the API did not return.
+const RELOAD_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Local copy of the Todoist projects and sections.
+///
+/// The whole catalog is reloaded once its TTL expires and is kept up
+/// to date in between by the `project:*` and `section:*` events, so
+/// enrichment does not need to call the Todoist API. When a reload
+/// fails the previous copy keeps being served, and the reload is only
+/// retried after a backoff so an unreachable API does not slow down
+/// every webhook. Projects and sections missing from the copy are
+/// looked up on their own, an id the API does not return being
+/// remembered for the same backoff.
+pub struct TodoistCatalog {
+    client: reqwest::Client,
+    api_url: String,
+    access_token: String,
+    ttl: Duration,
+    state: RwLock<CatalogState>,
+    refresh: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+struct CatalogState {
+    projects: HashMap<String, TodoistProject>,
+    sections: HashMap<String, TodoistSection>,
+    /// When the lookup of each API path last failed.
+    missing: HashMap<String, Instant>,
+    refreshed_at: Option<Instant>,
+    failed_at: Option<Instant>,
+}
+
+impl CatalogState {
+    fn is_missing(&self, path: &str) -> bool {
+        self.missing
+            .get(path)
+            .is_some_and(|at| at.elapsed() < RELOAD_BACKOFF)
+    }
+}
+
+impl TodoistCatalog {
+    pub fn new(
+        api_url: String,
+        access_token: String,
+        ttl: Duration,
+    ) -> Self {
+        TodoistCatalog {
+            // Only fails where `reqwest::Client::new` panics.
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("Failed to build the Todoist client"),
+            api_url,
+            access_token,
+            ttl,
+            state: RwLock::new(CatalogState::default()),
+            refresh: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Reloads the catalog if it is empty or older than its TTL.
+    ///
+    /// Only fails when there is no previous copy to fall back on.
+    pub async fn ensure_fresh(&self) -> Result<()> {
+        if !self.is_stale() {
+            return Ok(());
+        }
+
+        // Concurrent callers use the previous copy, or wait for the
+        // ongoing reload when there is none, instead of issuing their
+        // own.
+        let _guard = match self.refresh.try_lock() {
+            Ok(guard) => guard,
+            Err(_) if self.is_loaded() => return Ok(()),
+            Err(_) => self.refresh.lock().await,
+        };
+        if !self.is_stale() {
+            return Ok(());
+        }
+        if self.is_backing_off() {
+            return match self.is_loaded() {
+                true => Ok(()),
+                false => Err(anyhow!(
+                    "Todoist catalog unavailable, its last reload failed"
+                )),
+            };
+        }
+
+        match self.reload().await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.write().failed_at = Some(Instant::now());
+                if !self.is_loaded() {
+                    return Err(e);
+                }
+                warn!(
+                    "Todoist catalog refresh failed, serving stale copy for {:?}: {:#}",
+                    RELOAD_BACKOFF, e
+                );
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn project(&self, id: &str) -> Option<TodoistProject> {
+        let path = format!("projects/{}", id);
+        {
+            let state = self.read();
+            if let Some(project) = state.projects.get(id) {
+                return Some(project.clone());
+            }
+            if state.is_missing(&path) {
+                return None;
+            }
+        }
+
+        match self.fetch::<TodoistProject>(&path).await {
+            Ok(project) => {
+                self.write()
+                    .projects
+                    .insert(project.id.clone(), project.clone());
+                Some(project)
+            }
+            Err(e) => {
+                debug!("Todoist project {} not found: {:#}", id, e);
+                self.write().missing.insert(path, Instant::now());
+                None
+            }
+        }
+    }
+
+    pub async fn section(&self, id: &str) -> Option<TodoistSection> {
+        let path = format!("sections/{}", id);
+        {
+            let state = self.read();
+            if let Some(section) = state.sections.get(id) {
+                return Some(section.clone());
+            }
+            if state.is_missing(&path) {
+                return None;
+            }
+        }
+
+        match self.fetch::<TodoistSection>(&path).await {
+            Ok(section) => {
+                self.write()
+                    .sections
+                    .insert(section.id.clone(), section.clone());
+                Some(section)
+            }
+            Err(e) => {
+                debug!("Todoist section {} not found: {:#}", id, e);
+                self.write().missing.insert(path, Instant::now());
+                None
+            }
+        }
+    }
+
+    /// Resolves the ancestors of `project`, returning the chain from
+    /// the root project down to `project` itself.
+    ///
+    /// The walk stops at the first unknown parent or when a project is
+    /// seen twice, so a corrupted hierarchy cannot loop forever.
+    pub async fn ancestry(
+        &self,
+        project: TodoistProject,
+    ) -> Vec<TodoistProject> {
+        let mut seen = HashSet::from([project.id.clone()]);
+        let mut parent_id = project.parent_id.clone();
+        let mut chain = vec![project];
+
+        while let Some(id) = parent_id {
+            if !seen.insert(id.clone()) {
+                warn!("Todoist project {} is its own ancestor", id);
+                break;
+            }
+            match self.project(&id).await {
+                Some(parent) => {
+                    parent_id = parent.parent_id.clone();
+                    chain.push(parent);
+                }
+                None => break,
+            }
+        }
+
+        chain.reverse();
+        chain
+    }
+
+    /// Updates the catalog with the content of a project or section
+    /// event, other events are ignored.
+    pub fn apply(&self, event: &TodoistEvent) {
+        let (kind, action) = match event.event_name.split_once(':') {
+            Some(split) => split,
+            None => return,
+        };
+        let removed = matches!(action, "deleted" | "archived");
+
+        match kind {
+            "project" => {
+                let project: TodoistProject =
+                    match serde_json::from_value(
+                        event.event_data.clone(),
+                    ) {
+                        Ok(project) => project,
+                        Err(_) => return,
+                    };
+                let mut state = self.write();
+                if removed {
+                    state.projects.remove(&project.id);
+                } else {
+                    state
+                        .projects
+                        .insert(project.id.clone(), project);
+                }
+            }
+            "section" => {
+                let section: TodoistSection =
+                    match serde_json::from_value(
+                        event.event_data.clone(),
+                    ) {
+                        Ok(section) => section,
+                        Err(_) => return,
+                    };
+                let mut state = self.write();
+                if removed {
+                    state.sections.remove(&section.id);
+                } else {
+                    state
+                        .sections
+                        .insert(section.id.clone(), section);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn reload(&self) -> Result<()> {
+        let projects: Vec<TodoistProject> =
+            self.fetch("projects").await?;
+        let sections: Vec<TodoistSection> =
+            self.fetch("sections").await?;
+        debug!(
+            "Todoist catalog reloaded: {} projects, {} sections",
+            projects.len(),
+            sections.len()
+        );
+
+        let mut state = self.write();
+        state.projects =
+            projects.into_iter().map(|p| (p.id.clone(), p)).collect();
+        state.sections =
+            sections.into_iter().map(|s| (s.id.clone(), s)).collect();
+        state.missing.clear();
+        state.refreshed_at = Some(Instant::now());
+        state.failed_at = None;
+        Ok(())
+    }
+
+    async fn fetch<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<T> {
+        Ok(self
+            .client
+            .get(format!("{}/{}", self.api_url, path))
+            .header(
+                AUTHORIZATION,
+                format!("Bearer {}", self.access_token),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    fn is_stale(&self) -> bool {
+        match self.read().refreshed_at {
+            None => true,
+            Some(at) => at.elapsed() >= self.ttl,
+        }
+    }
+
+    fn is_backing_off(&self) -> bool {
+        self.read()
+            .failed_at
+            .is_some_and(|at| at.elapsed() < RELOAD_BACKOFF)
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.read().refreshed_at.is_some()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, CatalogState> {
+        self.state.read().unwrap()
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, CatalogState> {
+        self.state.write().unwrap()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use serde_json::{json, Value};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::*;
+
+    /// Todoist API on a local port serving `projects` and
+    /// `sections`, recording the requested paths. Tests may change
+    /// the served projects.
+    pub(crate) struct TodoistApi {
+        pub(crate) url: String,
+        pub(crate) projects: Arc<Mutex<Value>>,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TodoistApi {
+        pub(crate) fn start(
+            projects: Value,
+            sections: Value,
+        ) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url =
+                format!("http://{}", listener.local_addr().unwrap());
+            let projects = Arc::new(Mutex::new(projects));
+            let requests = Arc::new(Mutex::new(Vec::new()));
+
+            let (served, recorded) =
+                (projects.clone(), requests.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(&stream);
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                    }
+
+                    let path = request_line
+                        .split(' ')
+                        .nth(1)
+                        .unwrap_or("")
+                        .to_string();
+                    recorded.lock().unwrap().push(path.clone());
+                    let body = match path.as_str() {
+                        "/projects" => {
+                            Some(served.lock().unwrap().clone())
+                        }
+                        "/sections" => Some(sections.clone()),
+                        path => path
+                            .strip_prefix("/projects/")
+                            .and_then(|id| {
+                                find(&served.lock().unwrap(), id)
+                            }),
+                    };
+                    let (status, body) = match body {
+                        Some(body) => ("200 OK", body.to_string()),
+                        None => ("404 Not Found", String::new()),
+                    };
+                    write!(
+                        stream,
+                        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    )
+                    .unwrap();
+                }
+            });
+
+            TodoistApi {
+                url,
+                projects,
+                requests,
+            }
+        }
+
+        /// Paths requested so far, in order.
+        pub(crate) fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    fn find(resources: &Value, id: &str) -> Option<Value> {
+        resources
+            .as_array()?
+            .iter()
+            .find(|resource| resource["id"] == id)
+            .cloned()
+    }
+
+    /// Projects named after their id, each the child of the
+    /// previous one.
+    pub(crate) fn project_chain(ids: &[&str]) -> Value {
+        let mut parent_id = None;
+        ids.iter()
+            .map(|id| {
+                let project = json!({
+                    "id": id,
+                    "name": format!("P{}", id),
+                    "parent_id": parent_id,
+                });
+                parent_id = Some(id);
+                project
+            })
+            .collect()
+    }
+
+    fn catalog(api: &TodoistApi) -> TodoistCatalog {
+        TodoistCatalog::new(
+            api.url.clone(),
+            "token".to_string(),
+            Duration::from_secs(600),
+        )
+    }
+
+    fn ids(chain: &[TodoistProject]) -> Vec<&str> {
+        chain.iter().map(|project| project.id.as_str()).collect()
+    }
+
+    /// Answers every request with a 500, counting them.
+    fn failing_api() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url =
+            format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                counted.fetch_add(1, Ordering::SeqCst);
+                write!(
+                    stream,
+                    "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[actix_web::test]
+    async fn backs_off_after_failed_reload() {
+        let (url, requests) = failing_api();
+        let catalog = TodoistCatalog::new(
+            url,
+            "token".to_string(),
+            Duration::from_secs(600),
+        );
+
+        assert!(catalog.ensure_fresh().await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(catalog.ensure_fresh().await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn ancestry_walks_up_to_the_root() {
+        let api = TodoistApi::start(
+            project_chain(&["1", "2", "3", "4", "5"]),
+            json!([]),
+        );
+        let catalog = catalog(&api);
+
+        // Unloaded, each parent is fetched on its own.
+        let project = catalog.project("5").await.unwrap();
+        let chain = catalog.ancestry(project).await;
+        assert_eq!(ids(&chain), ["1", "2", "3", "4", "5"]);
+        assert_eq!(
+            api.requests(),
+            [
+                "/projects/5",
+                "/projects/4",
+                "/projects/3",
+                "/projects/2",
+                "/projects/1"
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn ancestry_stops_when_a_project_is_its_own_ancestor() {
+        let mut projects = project_chain(&["1", "2", "3", "4"]);
+        projects[0]["parent_id"] = json!("4");
+        let api = TodoistApi::start(projects, json!([]));
+        let catalog = catalog(&api);
+        catalog.ensure_fresh().await.unwrap();
+
+        let project = catalog.project("2").await.unwrap();
+        let chain = catalog.ancestry(project).await;
+        assert_eq!(ids(&chain), ["3", "4", "1", "2"]);
+        assert_eq!(api.requests(), ["/projects", "/sections"]);
+    }
+
+    fn event(name: &str, data: Value) -> TodoistEvent {
+        serde_json::from_value(json!({
+            "user_id": "42",
+            "version": "9",
+            "initiator": {"id": "42"},
+            "event_name": name,
+            "event_data": data,
+        }))
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn reloads_once_the_ttl_expires() {
+        let api = TodoistApi::start(project_chain(&["1"]), json!([]));
+        let catalog = TodoistCatalog::new(
+            api.url.clone(),
+            "token".to_string(),
+            Duration::from_millis(100),
+        );
+        catalog.ensure_fresh().await.unwrap();
+        *api.projects.lock().unwrap() = json!([{"id": "1", "name": "Renamed", "parent_id": null}]);
+
+        catalog.ensure_fresh().await.unwrap();
+        assert_eq!(catalog.project("1").await.unwrap().name, "P1");
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        catalog.ensure_fresh().await.unwrap();
+        assert_eq!(
+            catalog.project("1").await.unwrap().name,
+            "Renamed"
+        );
+        assert_eq!(
+            api.requests(),
+            ["/projects", "/sections", "/projects", "/sections"]
+        );
+    }
+
+    #[actix_web::test]
+    async fn applies_project_and_section_events_in_place() {
+        let api = TodoistApi::start(
+            project_chain(&["1", "2"]),
+            json!([{"id": "10", "name": "Backlog"}]),
+        );
+        let catalog = catalog(&api);
+        catalog.ensure_fresh().await.unwrap();
+
+        catalog.apply(&event(
+            "project:updated",
+            json!({"id": "2", "name": "Renamed", "parent_id": "1"}),
+        ));
+        catalog.apply(&event(
+            "project:added",
+            json!({"id": "3", "name": "New", "parent_id": "2"}),
+        ));
+        catalog.apply(&event(
+            "section:updated",
+            json!({"id": "10", "name": "Doing"}),
+        ));
+        assert_eq!(
+            catalog.project("2").await.unwrap().name,
+            "Renamed"
+        );
+        assert_eq!(catalog.project("3").await.unwrap().name, "New");
+        assert_eq!(
+            catalog.section("10").await.unwrap().name,
+            "Doing"
+        );
+
+        catalog.apply(&event(
+            "project:deleted",
+            json!({"id": "1", "name": "P1", "parent_id": null}),
+        ));
+        catalog.apply(&event(
+            "project:archived",
+            json!({"id": "3", "name": "New", "parent_id": "2"}),
+        ));
+        catalog.apply(&event(
+            "section:deleted",
+            json!({"id": "10", "name": "Doing"}),
+        ));
+        catalog.apply(&event(
+            "item:deleted",
+            json!({"id": "2", "name": "Not a project"}),
+        ));
+        let state = catalog.read();
+        let projects: Vec<_> = state.projects.keys().collect();
+        assert_eq!(projects, ["2"]);
+        assert_eq!(state.projects["2"].name, "Renamed");
+        assert!(state.sections.is_empty());
+        drop(state);
+        assert_eq!(api.requests(), ["/projects", "/sections"]);
+    }
+
+    #[actix_web::test]
+    async fn remembers_ids_the_api_does_not_return() {
+        let api = TodoistApi::start(json!([]), json!([]));
+        let catalog = catalog(&api);
+
+        assert!(catalog.project("7").await.is_none());
+        assert!(catalog.project("7").await.is_none());
+        assert!(catalog.section("8").await.is_none());
+        assert!(catalog.section("8").await.is_none());
+        assert_eq!(api.requests(), ["/projects/7", "/sections/8"]);
+    }
+}
@@ -0,0 +1,466 @@
+use actix_web::HttpRequest;
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use data_encoding::BASE64;
+use log::debug;
+use serde_json::Value;
+
+use crate::errors::IngestError;
+use crate::services::WebhookSource;
+use crate::signature;
+
+mod catalog;
+
+pub use catalog::TodoistCatalog;
+
+#[derive(Deserialize, Clone)]
+pub struct TodoistConfig {
+    #[allow(dead_code)]
+    pub client_id: String,
+    pub client_secret: String,
+    pub access_token: String,
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    #[serde(default = "default_catalog_ttl_secs")]
+    pub catalog_ttl_secs: u64,
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TodoistEvent {
+    user_id: String,
+    version: String,
+    initiator: Value,
+    event_name: String,
+    event_data: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SectionOrItemEvent {
+    id: String,
+    name: Option<String>,
+    parent_id: Option<String>,
+    project_id: Option<String>,
+    section_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TodoistProject {
+    id: String,
+    name: String,
+    parent_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TodoistSection {
+    id: String,
+    name: String,
+}
+
+pub struct ExtractedAttributes {
+    /// Project of the event preceded by all its ancestors, root first.
+    pub ancestry: Vec<TodoistProject>,
+    pub section_name: String,
+}
+
+impl ExtractedAttributes {
+    fn into_map(self, event_name: &str) -> HashMap<String, String> {
+        // `project_name`, `parent_name` and `parent_parent_name` are
+        // kept for the consumers written before `project_path`.
+        let name_at = |depth: usize| {
+            self.ancestry
+                .iter()
+                .rev()
+                .nth(depth)
+                .map(|p| p.name.clone())
+                .unwrap_or_default()
+        };
+        let project_name = name_at(0);
+        let parent_name = name_at(1);
+        let parent_parent_name = name_at(2);
+
+        let project_path = self
+            .ancestry
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>()
+            .join("/");
+        let project_ids = self
+            .ancestry
+            .iter()
+            .map(|p| p.id.as_str())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        HashMap::from([
+            ("event_name".to_string(), event_name.to_string()),
+            ("project_name".to_string(), project_name),
+            ("parent_name".to_string(), parent_name),
+            ("parent_parent_name".to_string(), parent_parent_name),
+            ("project_path".to_string(), project_path),
+            ("project_ids".to_string(), project_ids),
+            ("section_name".to_string(), self.section_name),
+        ])
+    }
+}
+
+pub struct Todoist {
+    config: TodoistConfig,
+    catalog: TodoistCatalog,
+}
+
+impl Todoist {
+    pub fn new(config: TodoistConfig) -> Self {
+        let catalog = TodoistCatalog::new(
+            config.api_url.trim_end_matches('/').to_string(),
+            config.access_token.clone(),
+            Duration::from_secs(config.catalog_ttl_secs),
+        );
+        Todoist { config, catalog }
+    }
+}
+
+impl WebhookSource for Todoist {
+    const NAME: &'static str = "todoist";
+
+    // For simplicity, TodoistEvent only contains only some data
+    //  the raw body is used for the complete publishing.
+    type Event = TodoistEvent;
+
+    fn from_env() -> Result<Self> {
+        Ok(Todoist::new(
+            envy::prefixed("TODOIST_").from_env::<TodoistConfig>()?,
+        ))
+    }
+
+    fn verify(
+        &self,
+        req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<(), IngestError> {
+        authorize_request(body, req, &self.config.client_secret)
+            .map_err(IngestError::Unauthorized)
+    }
+
+    fn parse(
+        &self,
+        _req: &HttpRequest,
+        body: &[u8],
+    ) -> Result<TodoistEvent, IngestError> {
+        serde_json::from_slice(body)
+            .map_err(|e| IngestError::MalformedPayload(e.into()))
+    }
+
+    async fn extract_attributes(
+        &self,
+        event: &TodoistEvent,
+    ) -> Result<HashMap<String, String>, IngestError> {
+        self.catalog
+            .ensure_fresh()
+            .await
+            .map_err(IngestError::Upstream)?;
+
+        let attr = if event.event_name.starts_with("project:") {
+            extract_project_attributes(event, &self.catalog).await
+        } else {
+            extract_item_section_attributes(event, &self.catalog)
+                .await
+        }
+        .map_err(IngestError::MalformedPayload)?;
+
+        // Applied after the extraction so deleted projects and
+        // sections can still be resolved for their own event.
+        self.catalog.apply(event);
+
+        Ok(attr.into_map(&event.event_name))
+    }
+
+    fn ordering_key(&self, event: &TodoistEvent) -> Option<String> {
+        event
+            .event_data
+            .get("id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    }
+
+    fn topic(&self, _event: &TodoistEvent) -> String {
+        self.config.topic.clone()
+    }
+}
+
+async fn extract_project_attributes(
+    event: &TodoistEvent,
+    catalog: &TodoistCatalog,
+) -> Result<ExtractedAttributes> {
+    // take extract inner value
+    let project: TodoistProject =
+        serde_json::from_value(event.event_data.clone())
+            .context("Failed to extract project event")?;
+
+    Ok(ExtractedAttributes {
+        ancestry: catalog.ancestry(project).await,
+        section_name: "".to_string(),
+    })
+}
+
+async fn extract_item_section_attributes(
+    event: &TodoistEvent,
+    catalog: &TodoistCatalog,
+) -> Result<ExtractedAttributes> {
+    let event_data: SectionOrItemEvent =
+        serde_json::from_value(event.event_data.clone())
+            .context("Failed to extract section or item event")?;
+
+    let cur_project = match &event_data.project_id {
+        None => None,
+        Some(project_id) => catalog.project(project_id).await,
+    };
+
+    debug!("project: {:?}", cur_project);
+
+    let ancestry = match cur_project {
+        None => Vec::new(),
+        Some(project) => catalog.ancestry(project).await,
+    };
+
+    let section_name = if event.event_name.starts_with("section:") {
+        match event_data.name {
+            Some(name) => name,
+            None => catalog
+                .section(&event_data.id)
+                .await
+                .map(|s| s.name)
+                .unwrap_or_default(),
+        }
+    } else {
+        match &event_data.section_id {
+            None => "".to_string(),
+            Some(section_id) => catalog
+                .section(section_id)
+                .await
+                .map(|s| s.name)
+                .unwrap_or_default(),
+        }
+    };
+
+    Ok(ExtractedAttributes {
+        ancestry,
+        section_name,
+    })
+}
+
+fn authorize_request(
+    body: &[u8],
+    request: &HttpRequest,
+    client_secret: &str,
+) -> Result<()> {
+    let signature = request
+        .headers()
+        .get("X-Todoist-HMAC-SHA256")
+        .ok_or(anyhow!("Missing header."))?;
+    let tag = BASE64
+        .decode(signature.as_bytes())
+        .map_err(|_| anyhow!("Malformed signature."))?;
+
+    if signature::verify_hmac_sha256(client_secret, body, &tag) {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid Signature."))
+    }
+}
+
+fn default_topic() -> String {
+    "todoist".to_string()
+}
+
+fn default_catalog_ttl_secs() -> u64 {
+    60 * 10
+}
+
+fn default_api_url() -> String {
+    "https://api.todoist.com/rest/v2".to_string()
+}
+
+#[cfg(all(test, feature = "pubsub"))]
+mod tests {
+    use actix_web::test;
+    use cloud_pubsub::MessageSubscriber;
+    use serde_json::json;
+
+    use super::catalog::tests::{project_chain, TodoistApi};
+    use super::*;
+    use crate::services::testing::{self, hmac_sha256};
+
+    const CLIENT_SECRET: &str = "secret";
+
+    fn todoist_api() -> TodoistApi {
+        TodoistApi::start(
+            json!([
+                {"id": "1", "name": "Work", "parent_id": null},
+                {"id": "2", "name": "Clients", "parent_id": "1"},
+            ]),
+            json!([{"id": "10", "name": "Backlog"}]),
+        )
+    }
+
+    fn todoist(api: &TodoistApi) -> Todoist {
+        Todoist::new(TodoistConfig {
+            client_id: "client".to_string(),
+            client_secret: CLIENT_SECRET.to_string(),
+            access_token: "token".to_string(),
+            topic: "todoist".to_string(),
+            catalog_ttl_secs: 600,
+            api_url: api.url.clone(),
+        })
+    }
+
+    fn item_added() -> Vec<u8> {
+        item_added_to("2")
+    }
+
+    fn item_added_to(project_id: &str) -> Vec<u8> {
+        json!({
+            "user_id": "42",
+            "version": "9",
+            "initiator": {"id": "42"},
+            "event_name": "item:added",
+            "event_data": {
+                "id": "100",
+                "content": "Call Acme",
+                "project_id": project_id,
+                "section_id": "10",
+            },
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn sign(body: &[u8]) -> String {
+        BASE64.encode(&hmac_sha256(CLIENT_SECRET, body))
+    }
+
+    #[actix_web::test]
+    async fn publishes_signed_webhook_with_its_attributes() {
+        let (app, pubsub) =
+            testing::app(todoist(&todoist_api())).await;
+        pubsub.create_subscription("todoist-archive", "todoist");
+
+        let body = item_added();
+        let req = test::TestRequest::post()
+            .uri("/todoist/webhook")
+            .insert_header(("X-Todoist-HMAC-SHA256", sign(&body)))
+            .set_payload(body.clone())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+
+        let published = pubsub.published_to("todoist");
+        assert_eq!(published.len(), 1);
+        let message = &published[0];
+        assert_eq!(message.data, body);
+        assert_eq!(message.ordering_key.as_deref(), Some("100"));
+        assert_eq!(message.attributes["event_name"], "item:added");
+        assert_eq!(
+            message.attributes["project_path"],
+            "Work/Clients"
+        );
+        assert_eq!(message.attributes["project_ids"], "1/2");
+        assert_eq!(message.attributes["project_name"], "Clients");
+        assert_eq!(message.attributes["parent_name"], "Work");
+        assert_eq!(message.attributes["section_name"], "Backlog");
+
+        let pulled =
+            pubsub.pull("todoist-archive", 10).await.unwrap();
+        assert_eq!(pulled.len(), 1);
+        assert_eq!(pulled[0].message_id, message.message_id);
+        assert_eq!(pubsub.outstanding("todoist-archive"), 1);
+        pubsub
+            .acknowledge(
+                "todoist-archive",
+                vec![pulled[0].ack_id.clone()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(pubsub.outstanding("todoist-archive"), 0);
+        assert!(pubsub
+            .pull("todoist-archive", 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_signature() {
+        let (app, pubsub) =
+            testing::app(todoist(&todoist_api())).await;
+
+        let req = test::TestRequest::post()
+            .uri("/todoist/webhook")
+            .insert_header((
+                "X-Todoist-HMAC-SHA256",
+                sign(b"other body"),
+            ))
+            .set_payload(item_added())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 401);
+
+        let req = test::TestRequest::post()
+            .uri("/todoist/webhook")
+            .insert_header(("X-Todoist-HMAC-SHA256", "not base64"))
+            .set_payload(item_added())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 401);
+        assert!(pubsub.published().is_empty());
+    }
+
+    /// Attributes published for an item added to `project_id`.
+    async fn published_attributes(
+        projects: Value,
+        project_id: &str,
+    ) -> HashMap<String, String> {
+        let api = TodoistApi::start(projects, json!([]));
+        let (app, pubsub) = testing::app(todoist(&api)).await;
+
+        let body = item_added_to(project_id);
+        let req = test::TestRequest::post()
+            .uri("/todoist/webhook")
+            .insert_header(("X-Todoist-HMAC-SHA256", sign(&body)))
+            .set_payload(body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+
+        let mut published = pubsub.published_to("todoist");
+        assert_eq!(published.len(), 1);
+        published.remove(0).attributes
+    }
+
+    #[actix_web::test]
+    async fn publishes_project_path_from_the_root() {
+        let projects = project_chain(&["1", "2", "3", "4", "5"]);
+        let attributes = published_attributes(projects, "5").await;
+
+        assert_eq!(attributes["project_path"], "P1/P2/P3/P4/P5");
+        assert_eq!(attributes["project_ids"], "1/2/3/4/5");
+        assert_eq!(attributes["project_name"], "P5");
+        assert_eq!(attributes["parent_name"], "P4");
+    }
+
+    #[actix_web::test]
+    async fn publishes_despite_a_cyclic_hierarchy() {
+        let mut projects = project_chain(&["1", "2", "3"]);
+        projects[0]["parent_id"] = json!("3");
+        let attributes = published_attributes(projects, "3").await;
+
+        assert_eq!(attributes["project_path"], "P1/P2/P3");
+        assert_eq!(attributes["project_ids"], "1/2/3");
+    }
+}
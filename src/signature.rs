@@ -0,0 +1,21 @@
+#[cfg(feature = "http")]
+use data_encoding::BASE64;
+use ring::hmac;
+
+/// Base64 HMAC-SHA256 of `body`, the signature scheme of the Todoist
+/// webhooks, reused to sign the forwarded events.
+#[cfg(feature = "http")]
+pub fn hmac_sha256(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    BASE64.encode(hmac::sign(&key, body).as_ref())
+}
+
+/// Checks a raw HMAC-SHA256 `tag` of `body` in constant time.
+pub fn verify_hmac_sha256(
+    secret: &str,
+    body: &[u8],
+    tag: &[u8],
+) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::verify(&key, body, tag).is_ok()
+}
@@ -0,0 +1,41 @@
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+use crate::event::IngestedEvent;
+
+/// JSON form of an event written or sent by the sinks.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    source: &'a str,
+    topic: &'a str,
+    #[serde(with = "time::serde::rfc3339")]
+    received_at: OffsetDateTime,
+    ordering_key: Option<&'a str>,
+    dedup_id: Option<&'a str>,
+    attributes: &'a HashMap<String, String>,
+    /// The body itself when it is JSON.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<&'a RawValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_base64: Option<String>,
+}
+
+pub fn to_json(event: &IngestedEvent) -> serde_json::Result<Vec<u8>> {
+    let payload =
+        serde_json::from_slice::<&RawValue>(&event.payload).ok();
+    serde_json::to_vec(&Envelope {
+        source: &event.source,
+        topic: &event.topic,
+        received_at: event.received_at,
+        ordering_key: event.ordering_key.as_deref(),
+        dedup_id: event.dedup_id.as_deref(),
+        attributes: &event.attributes,
+        payload,
+        payload_base64: match payload {
+            Some(_) => None,
+            None => Some(base64::encode(&event.payload)),
+        },
+    })
+}
@@ -0,0 +1,308 @@
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future::{self, BoxFuture, FutureExt};
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+use super::{envelope, EventSink};
+use crate::event::IngestedEvent;
+
+#[derive(Deserialize, Clone)]
+pub struct FileConfig {
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// Name of the file being written, `{prefix}.jsonl`, and of the
+    /// rotated ones, `{prefix}-{time}.jsonl`.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// The file is rotated once it reaches this size.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// The file is rotated on the first event after it has been open
+    /// this long, never when unset.
+    pub max_age_secs: Option<u64>,
+    /// Compresses the rotated files to `.jsonl.gz`.
+    #[serde(default)]
+    pub gzip: bool,
+}
+
+/// Appends the events to a JSONL file, one envelope per line: source,
+/// topic, reception time, ordering key, attributes and the body as
+/// `payload`, or as `payload_base64` when it is not JSON.
+///
+/// The file is rotated by size and age, the rotated files being named
+/// after the time of the rotation and optionally gzipped in the
+/// background. Meant for local runs without any broker, and as a raw
+/// archive. The ids number the events written since the start.
+pub struct FileSink {
+    config: FileConfig,
+    current: Mutex<Current>,
+}
+
+struct Current {
+    file: File,
+    len: u64,
+    opened_at: Instant,
+    written: u64,
+}
+
+impl FileSink {
+    pub fn open(config: FileConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir).with_context(|| {
+            format!("Failed to create the directory {}", config.dir)
+        })?;
+        let current = Current::open(&current_path(&config))?;
+        Ok(FileSink {
+            config,
+            current: Mutex::new(current),
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        FileSink::open(
+            envy::prefixed("FILE_").from_env::<FileConfig>()?,
+        )
+    }
+
+    fn append(&self, event: &IngestedEvent) -> Result<String> {
+        let mut line = envelope::to_json(event)?;
+        line.push(b'\n');
+
+        let mut current = self.current.lock().unwrap();
+        if self.is_due(&current) {
+            self.rotate(&mut current)?;
+        }
+        current.file.write_all(&line)?;
+        current.len += line.len() as u64;
+        current.written += 1;
+        Ok(current.written.to_string())
+    }
+
+    fn is_due(&self, current: &Current) -> bool {
+        let too_old = self.config.max_age_secs.is_some_and(|secs| {
+            current.opened_at.elapsed() >= Duration::from_secs(secs)
+        });
+        current.len > 0
+            && (current.len >= self.config.max_bytes || too_old)
+    }
+
+    /// Moves the current file aside and opens a new one.
+    fn rotate(&self, current: &mut Current) -> Result<()> {
+        let path = current_path(&self.config);
+        let rotated = self.rotated_path();
+        fs::rename(&path, &rotated).with_context(|| {
+            format!("Failed to rotate {}", path.display())
+        })?;
+        log::info!("file sink rotated: {}", rotated.display());
+
+        if self.config.gzip {
+            std::thread::spawn(move || {
+                if let Err(e) = gzip(&rotated) {
+                    log::error!(
+                        "file sink compression of {} failed: {}",
+                        rotated.display(),
+                        e
+                    );
+                }
+            });
+        }
+        *current = Current {
+            written: current.written,
+            ..Current::open(&path)?
+        };
+        Ok(())
+    }
+
+    fn rotated_path(&self) -> PathBuf {
+        let now = OffsetDateTime::now_utc();
+        let stamp = format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+            now.year(),
+            u8::from(now.month()),
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second()
+        );
+        // Rotations within the same second get a counter.
+        let dir = Path::new(&self.config.dir);
+        (0..)
+            .map(|n| match n {
+                0 => format!("{}-{}", self.config.prefix, stamp),
+                n => {
+                    format!("{}-{}-{}", self.config.prefix, stamp, n)
+                }
+            })
+            .find(|name| {
+                !dir.join(format!("{}.jsonl", name)).exists()
+                    && !dir
+                        .join(format!("{}.jsonl.gz", name))
+                        .exists()
+            })
+            .map(|name| dir.join(format!("{}.jsonl", name)))
+            .unwrap()
+    }
+}
+
+impl Current {
+    fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                format!("Failed to open {}", path.display())
+            })?;
+        Ok(Current {
+            len: file.metadata()?.len(),
+            file,
+            opened_at: Instant::now(),
+            written: 0,
+        })
+    }
+}
+
+impl EventSink for FileSink {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        future::ready(self.append(event)).boxed()
+    }
+}
+
+fn current_path(config: &FileConfig) -> PathBuf {
+    Path::new(&config.dir).join(format!("{}.jsonl", config.prefix))
+}
+
+/// Replaces `path` with its gzipped copy, which only shows up under its
+/// final name once complete.
+fn gzip(path: &Path) -> io::Result<()> {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".gz");
+    let mut partial = compressed.clone();
+    partial.push(".partial");
+
+    let mut encoder = GzEncoder::new(
+        File::create(&partial)?,
+        Compression::default(),
+    );
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::rename(&partial, &compressed)?;
+    fs::remove_file(path)
+}
+
+fn default_dir() -> String {
+    "events".to_string()
+}
+
+fn default_prefix() -> String {
+    "events".to_string()
+}
+
+fn default_max_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::thread;
+
+    use super::*;
+
+    fn event(payload: &[u8]) -> IngestedEvent {
+        IngestedEvent {
+            source: "todoist".to_string(),
+            topic: "todoist".to_string(),
+            payload: payload.to_vec(),
+            attributes: HashMap::from([(
+                "event_name".to_string(),
+                "item:added".to_string(),
+            )]),
+            ordering_key: Some("100".to_string()),
+            dedup_id: None,
+            received_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[actix_web::test]
+    async fn rotates_and_compresses() {
+        let dir = std::env::temp_dir()
+            .join(format!("file-sink-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let sink = FileSink::open(FileConfig {
+            dir: dir.to_str().unwrap().to_string(),
+            prefix: "events".to_string(),
+            max_bytes: 1,
+            max_age_secs: None,
+            gzip: true,
+        })
+        .unwrap();
+
+        assert_eq!(
+            sink.publish(&event(b"{\"id\": 1}")).await.unwrap(),
+            "1"
+        );
+        assert_eq!(
+            sink.publish(&event(b"not json")).await.unwrap(),
+            "2"
+        );
+
+        let current =
+            fs::read_to_string(dir.join("events.jsonl")).unwrap();
+        let line: serde_json::Value =
+            serde_json::from_str(&current).unwrap();
+        assert_eq!(
+            line["payload_base64"],
+            base64::encode("not json")
+        );
+        assert_eq!(line["ordering_key"], "100");
+        assert_eq!(line["attributes"]["event_name"], "item:added");
+
+        for _ in 0..100 {
+            let names = files(&dir);
+            if names.len() == 2 && names[0].ends_with(".gz") {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let names = files(&dir);
+        assert_eq!(names.len(), 2, "{:?}", names);
+        assert!(
+            names[0].starts_with("events-")
+                && names[0].ends_with(".jsonl.gz")
+        );
+
+        let mut rotated = String::new();
+        io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(
+                File::open(dir.join(&names[0])).unwrap(),
+            ),
+            &mut rotated,
+        )
+        .unwrap();
+        let line: serde_json::Value =
+            serde_json::from_str(&rotated).unwrap();
+        assert_eq!(line["payload"]["id"], 1);
+        assert_eq!(line["source"], "todoist");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,383 @@
+use anyhow::{anyhow, Context, Result};
+use futures::future::{self, BoxFuture, FutureExt};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{envelope, EventSink};
+use crate::event::IngestedEvent;
+use crate::signature;
+
+const SIGNATURE_HEADER: &str = "X-Ingestor-HMAC-SHA256";
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+#[derive(Deserialize, Clone)]
+pub struct HttpConfig {
+    /// Destinations receiving every event, e.g.
+    /// `https://a.internal/events,https://b.internal/hook`.
+    pub urls: Vec<String>,
+    /// Signs the bodies when set.
+    pub secret: Option<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Attempts per destination and event, the first one included.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Consecutive failed events opening the circuit of a destination.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Time an open circuit fails the events right away, before one
+    /// event is let through to probe the destination.
+    #[serde(default = "default_open_secs")]
+    pub open_secs: u64,
+}
+
+/// POSTs the JSON envelope of each event to every destination.
+///
+/// With a secret, the body is signed with a base64 HMAC-SHA256 in the
+/// `X-Ingestor-HMAC-SHA256` header, the way Todoist signs its own
+/// webhooks. Connection errors, timeouts, `429` and `5xx` answers are
+/// retried with a growing delay. A destination failing too many events
+/// in a row has its circuit opened, failing the next events without
+/// calling it. An event is published once every destination accepted
+/// it, a retry of the event going to all of them again.
+pub struct HttpSink {
+    client: reqwest::Client,
+    secret: Option<String>,
+    max_attempts: u32,
+    destinations: Vec<Arc<Destination>>,
+}
+
+struct Destination {
+    url: String,
+    breaker: Mutex<Breaker>,
+    failure_threshold: u32,
+    open_for: Duration,
+}
+
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl HttpSink {
+    pub fn new(config: HttpConfig) -> Result<Self> {
+        if config.urls.is_empty() {
+            return Err(anyhow!(
+                "The http sink needs at least one URL"
+            ));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        let destinations = config
+            .urls
+            .iter()
+            .map(|url| {
+                Arc::new(Destination {
+                    url: url.clone(),
+                    breaker: Mutex::new(Breaker::default()),
+                    failure_threshold: config
+                        .failure_threshold
+                        .max(1),
+                    open_for: Duration::from_secs(config.open_secs),
+                })
+            })
+            .collect();
+        Ok(HttpSink {
+            client,
+            secret: config.secret,
+            max_attempts: config.max_attempts.max(1),
+            destinations,
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        HttpSink::new(
+            envy::prefixed("HTTP_SINK_").from_env::<HttpConfig>()?,
+        )
+    }
+}
+
+impl EventSink for HttpSink {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        let body = match envelope::to_json(event) {
+            Ok(body) => body,
+            Err(e) => return future::ready(Err(e.into())).boxed(),
+        };
+        let signature = self
+            .secret
+            .as_ref()
+            .map(|secret| signature::hmac_sha256(secret, &body));
+
+        let sends: Vec<_> = self
+            .destinations
+            .iter()
+            .map(|destination| {
+                destination.clone().send(
+                    self.client.clone(),
+                    body.clone(),
+                    signature.clone(),
+                    self.max_attempts,
+                )
+            })
+            .collect();
+
+        async move {
+            let statuses = future::try_join_all(sends).await?;
+            Ok(statuses
+                .iter()
+                .map(|status| status.as_u16().to_string())
+                .collect::<Vec<_>>()
+                .join(","))
+        }
+        .boxed()
+    }
+}
+
+impl Destination {
+    async fn send(
+        self: Arc<Self>,
+        client: reqwest::Client,
+        body: Vec<u8>,
+        signature: Option<String>,
+        max_attempts: u32,
+    ) -> Result<StatusCode> {
+        {
+            let mut breaker = self.breaker.lock().unwrap();
+            if let Some(until) = breaker.open_until {
+                let now = Instant::now();
+                if now < until {
+                    return Err(anyhow!(
+                        "Circuit of {} is open",
+                        self.url
+                    ));
+                }
+                // This event probes the destination, the next ones fail
+                // right away until it succeeds.
+                breaker.open_until = Some(now + self.open_for);
+            }
+        }
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        let result = loop {
+            let mut request = client
+                .post(&self.url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            let (result, retryable) = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    (Ok(response.status()), false)
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    (
+                        Err(anyhow!(
+                            "{} answered {}",
+                            self.url,
+                            status
+                        )),
+                        status == StatusCode::TOO_MANY_REQUESTS
+                            || status.is_server_error(),
+                    )
+                }
+                Err(e) => (
+                    Err(e).with_context(|| {
+                        format!("Failed to POST to {}", self.url)
+                    }),
+                    true,
+                ),
+            };
+            if result.is_ok() || !retryable || attempt >= max_attempts
+            {
+                break result;
+            }
+
+            log::warn!(
+                "http sink attempt {} failed, retry_in={:?}: {:#}",
+                attempt,
+                backoff,
+                result.unwrap_err()
+            );
+            actix_web::rt::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        };
+
+        self.record(result.is_ok());
+        result
+    }
+
+    fn record(&self, success: bool) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if success {
+            *breaker = Breaker::default();
+            return;
+        }
+        breaker.failures += 1;
+        if breaker.failures >= self.failure_threshold {
+            log::error!(
+                "http sink circuit of {} opened for {:?}",
+                self.url,
+                self.open_for
+            );
+            breaker.open_until = Some(Instant::now() + self.open_for);
+        }
+    }
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_secs() -> u64 {
+    30
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    /// Signature header and body of each request received.
+    type Requests = mpsc::Receiver<(Option<String>, Vec<u8>)>;
+
+    /// Answers with `statuses` in turn.
+    fn destination(statuses: Vec<u16>) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/events",
+            listener.local_addr().unwrap()
+        );
+        let (requests, received) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(statuses)
+            {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut signature = None;
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(": ")
+                    {
+                        match name.to_lowercase().as_str() {
+                            "content-length" => {
+                                length = value.parse().unwrap()
+                            }
+                            "x-ingestor-hmac-sha256" => {
+                                signature = Some(value.to_string())
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                requests.send((signature, body)).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+
+        (url, received)
+    }
+
+    fn sink(urls: Vec<String>) -> HttpSink {
+        HttpSink::new(HttpConfig {
+            urls,
+            secret: Some("secret".to_string()),
+            timeout_ms: 5_000,
+            max_attempts: 2,
+            failure_threshold: 1,
+            open_secs: 60,
+        })
+        .unwrap()
+    }
+
+    fn event() -> IngestedEvent {
+        IngestedEvent {
+            source: "todoist".to_string(),
+            topic: "todoist".to_string(),
+            payload: b"{\"event_name\":\"item:added\"}".to_vec(),
+            attributes: HashMap::from([(
+                "project_name".to_string(),
+                "Work".to_string(),
+            )]),
+            ordering_key: Some("100".to_string()),
+            dedup_id: None,
+            received_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn signs_and_retries_server_errors() {
+        let (first, first_requests) = destination(vec![200]);
+        let (second, second_requests) = destination(vec![503, 204]);
+
+        let id = sink(vec![first, second])
+            .publish(&event())
+            .await
+            .unwrap();
+        assert_eq!(id, "200,204");
+
+        let (signature, body) = first_requests.recv().unwrap();
+        assert_eq!(
+            signature.as_deref(),
+            Some(signature::hmac_sha256("secret", &body).as_str())
+        );
+        let envelope: serde_json::Value =
+            serde_json::from_slice(&body).unwrap();
+        assert_eq!(envelope["payload"]["event_name"], "item:added");
+        assert_eq!(envelope["attributes"]["project_name"], "Work");
+        assert_eq!(envelope["ordering_key"], "100");
+        assert_eq!(second_requests.iter().take(2).count(), 2);
+    }
+
+    #[actix_web::test]
+    async fn opens_circuit_after_failures() {
+        let (url, requests) = destination(vec![400, 200]);
+        let sink = sink(vec![url]);
+
+        let error = sink.publish(&event()).await.unwrap_err();
+        assert!(error.to_string().contains("400"), "{}", error);
+        let error = sink.publish(&event()).await.unwrap_err();
+        assert!(error.to_string().contains("open"), "{}", error);
+        assert_eq!(requests.try_iter().count(), 1);
+    }
+}
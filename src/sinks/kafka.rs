@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use futures::future::{self, BoxFuture, FutureExt};
+use rdkafka::config::ClientConfig;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use serde::Deserialize;
+
+use super::EventSink;
+use crate::event::IngestedEvent;
+
+#[derive(Deserialize, Clone)]
+pub struct KafkaConfig {
+    /// Bootstrap servers, e.g. `localhost:9092`.
+    pub brokers: String,
+    /// Prepended to the topic of each event.
+    #[serde(default)]
+    pub topic_prefix: String,
+    /// Time given to the producer to get a write acknowledged,
+    /// retries included.
+    #[serde(default = "default_message_timeout_ms")]
+    pub message_timeout_ms: u64,
+}
+
+/// Produces the events to the Kafka topic they name.
+///
+/// The ordering key becomes the message key, so the events of an
+/// item land on the same partition in order, and the attributes and
+/// dedup id become headers. The producer is idempotent and waits for
+/// every in-sync replica, an event only counts as published once the
+/// delivery report confirms it.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic_prefix: String,
+}
+
+impl KafkaSink {
+    pub fn new(config: KafkaConfig) -> Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("enable.idempotence", "true")
+            .set("acks", "all")
+            .set("max.in.flight.requests.per.connection", "5")
+            .set(
+                "message.timeout.ms",
+                config.message_timeout_ms.to_string(),
+            )
+            .create()?;
+        Ok(KafkaSink {
+            producer,
+            topic_prefix: config.topic_prefix,
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        KafkaSink::new(
+            envy::prefixed("KAFKA_").from_env::<KafkaConfig>()?,
+        )
+    }
+}
+
+impl EventSink for KafkaSink {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        let topic = format!("{}{}", self.topic_prefix, event.topic);
+        let headers = event.message_attributes().iter().fold(
+            OwnedHeaders::new(),
+            |headers, (key, value)| {
+                headers.insert(Header {
+                    key,
+                    value: Some(value),
+                })
+            },
+        );
+        let mut record: FutureRecord<str, Vec<u8>> =
+            FutureRecord::to(&topic)
+                .payload(&event.payload)
+                .headers(headers);
+        if let Some(key) = &event.ordering_key {
+            record = record.key(key);
+        }
+
+        // Queued right away, the future only waits for the report.
+        let delivery = match self.producer.send_result(record) {
+            Ok(delivery) => delivery,
+            Err((e, _)) => {
+                return future::ready(Err(e.into())).boxed()
+            }
+        };
+        async move {
+            let (partition, offset) = delivery
+                .await
+                .map_err(|_| {
+                    anyhow!("Kafka producer dropped the message")
+                })?
+                .map_err(|(e, _)| e)?;
+            Ok(format!("{}:{}", partition, offset))
+        }
+        .boxed()
+    }
+}
+
+fn default_message_timeout_ms() -> u64 {
+    30_000
+}
+
+/// Runs against the broker of `docker-compose.yml`:
+/// `docker compose up -d kafka && cargo test --features kafka -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use rdkafka::consumer::{Consumer, StreamConsumer};
+    use rdkafka::message::{Headers, Message};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn brokers() -> String {
+        std::env::var("KAFKA_BROKERS")
+            .unwrap_or_else(|_| "localhost:9092".to_string())
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Kafka broker"]
+    async fn publishes_key_headers_and_payload() {
+        let topic = format!("ingestor-test-{}", std::process::id());
+        let sink = KafkaSink::new(KafkaConfig {
+            brokers: brokers(),
+            topic_prefix: String::new(),
+            message_timeout_ms: 10_000,
+        })
+        .unwrap();
+
+        let event = IngestedEvent {
+            source: "todoist".to_string(),
+            topic: topic.clone(),
+            payload: b"{\"event_name\":\"item:added\"}".to_vec(),
+            attributes: HashMap::from([
+                ("event_name".to_string(), "item:added".to_string()),
+                ("project_name".to_string(), "Work".to_string()),
+            ]),
+            ordering_key: Some("100".to_string()),
+            dedup_id: None,
+            received_at: time::OffsetDateTime::now_utc(),
+        };
+        let id = sink.publish(&event).await.unwrap();
+        assert!(id.contains(':'));
+
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers())
+            .set("group.id", &topic)
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer.subscribe(&[&topic]).unwrap();
+        let message = actix_web::rt::time::timeout(
+            Duration::from_secs(30),
+            consumer.recv(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(message.key(), Some(&b"100"[..]));
+        assert_eq!(message.payload(), Some(&event.payload[..]));
+        let headers: HashMap<_, _> = message
+            .headers()
+            .unwrap()
+            .iter()
+            .map(|h| (h.key.to_string(), h.value.unwrap().to_vec()))
+            .collect();
+        assert_eq!(headers["event_name"], b"item:added");
+        assert_eq!(headers["project_name"], b"Work");
+    }
+}
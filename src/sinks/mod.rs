@@ -0,0 +1,159 @@
+mod envelope;
+#[cfg(feature = "file")]
+pub mod file;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "pubsub")]
+pub mod pubsub;
+#[cfg(feature = "redis")]
+pub mod redis;
+mod stdout;
+
+use anyhow::{anyhow, Result};
+use futures::future::{self, BoxFuture, FutureExt};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::configs::IngestorConfig;
+use crate::event::IngestedEvent;
+
+pub use stdout::StdoutSink;
+
+/// Destination of the accepted events.
+///
+/// Each backend is behind its own cargo feature, apart from `stdout`,
+/// and picked by name in the configuration.
+pub trait EventSink: Send + Sync {
+    /// Hands `event` to the sink, the returned future resolving to the
+    /// id the sink gave it once it is stored.
+    ///
+    /// Some sinks, e.g. `pubsub` and `kafka`, queue the event before
+    /// returning, others only send it once the future is polled. A
+    /// caller keeping the events of an ordering key in order awaits
+    /// each one before handing over the next, as the outbox drain does.
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>>;
+}
+
+/// Sinks of the enabled sources, each event going to the sink of its
+/// source.
+pub struct Sinks {
+    default: Arc<dyn EventSink>,
+    by_source: HashMap<String, Arc<dyn EventSink>>,
+}
+
+impl Sinks {
+    /// Sends the events of every source to `sink`.
+    pub fn single(sink: Arc<dyn EventSink>) -> Self {
+        Sinks {
+            default: sink,
+            by_source: HashMap::new(),
+        }
+    }
+
+    /// Builds the sinks named in the configuration, each one once even
+    /// when several sources use it.
+    pub async fn from_config(
+        config: &IngestorConfig,
+    ) -> Result<Self> {
+        let mut built = HashMap::new();
+        let mut sinks =
+            Sinks::single(build(&config.sink, &mut built).await?);
+
+        for entry in &config.source_sinks {
+            let (source, sink) = entry.split_once('=').ok_or_else(|| {
+                anyhow!("Invalid source sink {}, expected source=sink", entry)
+            })?;
+            let sink = build(sink, &mut built).await?;
+            sinks.by_source.insert(source.to_string(), sink);
+        }
+        Ok(sinks)
+    }
+
+    fn get(&self, source: &str) -> &Arc<dyn EventSink> {
+        self.by_source.get(source).unwrap_or(&self.default)
+    }
+}
+
+impl EventSink for Sinks {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        self.get(&event.source).publish(event)
+    }
+}
+
+/// Sends each event to several sinks, e.g. `pubsub+http`.
+///
+/// The event is published once every sink stored it, a retry of the
+/// event going to all of them again.
+struct Fanout(Vec<Arc<dyn EventSink>>);
+
+impl EventSink for Fanout {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        let published: Vec<_> =
+            self.0.iter().map(|sink| sink.publish(event)).collect();
+        async move { Ok(future::try_join_all(published).await?.join("+")) }
+            .boxed()
+    }
+}
+
+/// Builds the sink named `spec`, names joined by `+` making a fan-out.
+async fn build(
+    spec: &str,
+    built: &mut HashMap<String, Arc<dyn EventSink>>,
+) -> Result<Arc<dyn EventSink>> {
+    let mut sinks = Vec::new();
+    for name in spec.split('+') {
+        sinks.push(build_one(name.trim(), built).await?);
+    }
+    Ok(match sinks.len() {
+        1 => sinks.remove(0),
+        _ => Arc::new(Fanout(sinks)),
+    })
+}
+
+async fn build_one(
+    name: &str,
+    built: &mut HashMap<String, Arc<dyn EventSink>>,
+) -> Result<Arc<dyn EventSink>> {
+    if let Some(sink) = built.get(name) {
+        return Ok(sink.clone());
+    }
+
+    let sink: Arc<dyn EventSink> = match name {
+        #[cfg(feature = "pubsub")]
+        "pubsub" => Arc::new(pubsub::PubSubSink::from_env().await?),
+        #[cfg(feature = "file")]
+        "file" => Arc::new(file::FileSink::from_env()?),
+        #[cfg(feature = "http")]
+        "http" => Arc::new(http::HttpSink::from_env()?),
+        #[cfg(feature = "kafka")]
+        "kafka" => Arc::new(kafka::KafkaSink::from_env()?),
+        #[cfg(feature = "nats")]
+        "nats" => Arc::new(nats::NatsSink::from_env().await?),
+        #[cfg(feature = "redis")]
+        "redis" => {
+            Arc::new(self::redis::RedisSink::from_env().await?)
+        }
+        "stdout" => Arc::new(StdoutSink::default()),
+        other => {
+            return Err(anyhow!(
+                "Unknown sink: {}, or its cargo feature is disabled",
+                other
+            ))
+        }
+    };
+    built.insert(name.to_string(), sink.clone());
+    Ok(sink)
+}
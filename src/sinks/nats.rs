@@ -0,0 +1,227 @@
+use anyhow::{Context as _, Result};
+use async_nats::jetstream::{self, stream, Context};
+use async_nats::{header, ConnectOptions, HeaderMap};
+use data_encoding::HEXLOWER;
+use futures::future::{BoxFuture, FutureExt};
+use ring::digest;
+use serde::Deserialize;
+
+use super::EventSink;
+use crate::event::IngestedEvent;
+
+#[derive(Deserialize, Clone)]
+pub struct NatsConfig {
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// Credentials file of the user, anonymous when unset.
+    pub creds_file: Option<String>,
+    #[serde(default = "default_subject_prefix")]
+    pub subject_prefix: String,
+    /// Stream created to hold the subjects of the prefix when it does
+    /// not exist, the streams being managed elsewhere when unset.
+    pub stream: Option<String>,
+}
+
+/// Publishes the events to JetStream.
+///
+/// The subject is made of the prefix, the source and the event name,
+/// e.g. `ingest.todoist.item.added`, and the attributes become
+/// headers. `Nats-Msg-Id` is a hash of the source and dedup id, or of
+/// the payload without one, so the server drops a webhook delivered
+/// twice within the duplicate window of the stream. The event is only
+/// sent once the future of `publish` is polled.
+pub struct NatsSink {
+    jetstream: Context,
+    subject_prefix: String,
+}
+
+impl NatsSink {
+    pub async fn connect(config: NatsConfig) -> Result<Self> {
+        let options = match &config.creds_file {
+            Some(path) => ConnectOptions::with_credentials_file(path)
+                .await
+                .context("Failed to read the NATS credentials")?,
+            None => ConnectOptions::new(),
+        };
+        let client = options
+            .connect(config.url.as_str())
+            .await
+            .context("Failed to connect to NATS")?;
+        let jetstream = jetstream::new(client);
+
+        if let Some(name) = config.stream {
+            jetstream
+                .get_or_create_stream(stream::Config {
+                    name,
+                    subjects: vec![format!(
+                        "{}.>",
+                        config.subject_prefix
+                    )],
+                    ..Default::default()
+                })
+                .await
+                .context("Failed to create the JetStream stream")?;
+        }
+
+        Ok(NatsSink {
+            jetstream,
+            subject_prefix: config.subject_prefix,
+        })
+    }
+
+    pub async fn from_env() -> Result<Self> {
+        NatsSink::connect(
+            envy::prefixed("NATS_").from_env::<NatsConfig>()?,
+        )
+        .await
+    }
+
+    fn subject(&self, event: &IngestedEvent) -> String {
+        let mut subject = format!(
+            "{}.{}",
+            self.subject_prefix,
+            subject_token(&event.source)
+        );
+        if let Some(name) = event.attributes.get("event_name") {
+            for part in name.split(':') {
+                subject.push('.');
+                subject.push_str(&subject_token(part));
+            }
+        }
+        subject
+    }
+}
+
+impl EventSink for NatsSink {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        let jetstream = self.jetstream.clone();
+        let subject = self.subject(event);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::NATS_MESSAGE_ID,
+            event_id(event).as_str(),
+        );
+        for (key, value) in &event.attributes {
+            // Line breaks would end the header block early.
+            headers.insert(
+                key.as_str(),
+                value.replace(['\r', '\n'], " ").as_str(),
+            );
+        }
+        let payload = event.payload.clone().into();
+
+        async move {
+            let ack = jetstream
+                .publish_with_headers(subject, headers, payload)
+                .await?
+                .await?;
+            Ok(format!("{}:{}", ack.stream, ack.sequence))
+        }
+        .boxed()
+    }
+}
+
+/// Same for every delivery of a webhook, through its dedup id or its
+/// identical body.
+fn event_id(event: &IngestedEvent) -> String {
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(event.source.as_bytes());
+    context.update(b"\n");
+    match &event.dedup_id {
+        Some(id) => context.update(id.as_bytes()),
+        None => context.update(&event.payload),
+    }
+    HEXLOWER.encode(context.finish().as_ref())
+}
+
+/// Keeps a name from adding tokens or wildcards to the subject.
+fn subject_token(name: &str) -> String {
+    let token: String = name
+        .chars()
+        .map(|c| match c {
+            '.' | '*' | '>' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect();
+    if token.is_empty() {
+        "_".to_string()
+    } else {
+        token
+    }
+}
+
+fn default_url() -> String {
+    "nats://localhost:4222".to_string()
+}
+
+fn default_subject_prefix() -> String {
+    "ingest".to_string()
+}
+
+/// Runs against the server of `docker-compose.yml`:
+/// `docker compose up -d nats && cargo test --features nats -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn url() -> String {
+        std::env::var("NATS_URL").unwrap_or_else(|_| default_url())
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a nats-server with JetStream"]
+    async fn publishes_once_with_subject_and_headers() {
+        let prefix = format!("ingest{}", std::process::id());
+        let sink = NatsSink::connect(NatsConfig {
+            url: url(),
+            creds_file: None,
+            subject_prefix: prefix.clone(),
+            stream: Some(prefix.clone()),
+        })
+        .await
+        .unwrap();
+
+        let client = async_nats::connect(url()).await.unwrap();
+        let mut subscriber =
+            client.subscribe(format!("{}.>", prefix)).await.unwrap();
+
+        let event = IngestedEvent {
+            source: "todoist".to_string(),
+            topic: "todoist".to_string(),
+            payload: b"{\"event_name\":\"item:added\"}".to_vec(),
+            attributes: HashMap::from([
+                ("event_name".to_string(), "item:added".to_string()),
+                ("project_name".to_string(), "Work".to_string()),
+            ]),
+            ordering_key: Some("100".to_string()),
+            dedup_id: None,
+            received_at: time::OffsetDateTime::now_utc(),
+        };
+        let first = sink.publish(&event).await.unwrap();
+        let second = sink.publish(&event).await.unwrap();
+        assert_eq!(first, second, "duplicate not dropped");
+
+        let message = subscriber.next().await.unwrap();
+        assert_eq!(
+            message.subject.as_str(),
+            format!("{}.todoist.item.added", prefix)
+        );
+        assert_eq!(message.payload.as_ref(), &event.payload[..]);
+        let headers = message.headers.unwrap();
+        assert_eq!(
+            headers.get("project_name").unwrap().as_str(),
+            "Work"
+        );
+        assert_eq!(
+            headers.get(header::NATS_MESSAGE_ID).unwrap().as_str(),
+            event_id(&event)
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{Context, Result};
+use cloud_pubsub::error::Error;
+use cloud_pubsub::{
+    Client, EncodedMessage, MessagePublisher, Publisher,
+    PublisherConfig,
+};
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::EventSink;
+use crate::configs::GoogleConfig;
+use crate::event::IngestedEvent;
+
+pub async fn new(google_config: GoogleConfig) -> Result<Client> {
+    let pubsub = match google_config.application_credentials {
+        Some(path) => Client::new(path).await,
+        None => Client::from_default_credentials().await,
+    }
+    .context("Failed to initialize pubsub")?;
+
+    pubsub.spawn_token_renew(Duration::from_secs(60 * 10));
+    Ok(pubsub)
+}
+
+/// Publishes the events to the Pub/Sub topic they name, the payload as
+/// data along with the attributes, dedup id included, and ordering key.
+pub struct PubSubSink {
+    publisher: Arc<dyn MessagePublisher>,
+}
+
+impl PubSubSink {
+    pub fn new(publisher: Arc<dyn MessagePublisher>) -> Self {
+        PubSubSink { publisher }
+    }
+
+    /// Batching publishers of the client configured by the `GOOGLE_`
+    /// variables.
+    pub async fn from_env() -> Result<Self> {
+        let google_config =
+            envy::prefixed("GOOGLE_").from_env::<GoogleConfig>()?;
+        let client = new(google_config).await?;
+        Ok(PubSubSink::new(Arc::new(Publishers::new(
+            client,
+            PublisherConfig::default(),
+        ))))
+    }
+}
+
+impl EventSink for PubSubSink {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        self.publisher
+            .publish(
+                &event.topic,
+                EncodedMessage::new_binary(
+                    &event.payload,
+                    Some(event.message_attributes()),
+                    event.ordering_key.clone(),
+                ),
+            )
+            .err_into()
+            .boxed()
+    }
+}
+
+/// Batching publishers, created on first use of each topic.
+///
+/// Events published at the same time, e.g. a burst of webhooks, share
+/// their publish requests.
+pub struct Publishers {
+    client: Client,
+    config: PublisherConfig,
+    publishers: Mutex<HashMap<String, Publisher>>,
+}
+
+impl Publishers {
+    pub fn new(client: Client, config: PublisherConfig) -> Self {
+        Publishers {
+            client,
+            config,
+            publishers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, topic: &str) -> Publisher {
+        self.publishers
+            .lock()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_insert_with(|| {
+                self.client
+                    .topic(topic.to_string())
+                    .publisher(self.config.clone())
+            })
+            .clone()
+    }
+}
+
+impl MessagePublisher for Publishers {
+    fn publish(
+        &self,
+        topic: &str,
+        message: EncodedMessage,
+    ) -> BoxFuture<'static, Result<String, Error>> {
+        self.get(topic).publish(message).boxed()
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::{Context, Result};
+use futures::future::{BoxFuture, FutureExt};
+use redis::aio::ConnectionManager;
+use redis::streams::StreamMaxlen;
+use redis::{AsyncCommands, RedisResult};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use super::EventSink;
+use crate::event::IngestedEvent;
+
+#[derive(Deserialize, Clone)]
+pub struct RedisConfig {
+    #[serde(default = "default_url")]
+    pub url: String,
+    /// Prepended to the source to name its stream.
+    #[serde(default = "default_stream_prefix")]
+    pub stream_prefix: String,
+    /// Streams are trimmed to about this many entries, never when
+    /// unset.
+    pub maxlen: Option<usize>,
+    /// Consumer group created on each stream before its first entry is
+    /// added, so its consumers get every event.
+    pub group: Option<String>,
+}
+
+/// Adds the events to a stream per source, e.g. `ingest:todoist`.
+///
+/// Each entry has a `payload` field with the body, an `ordering_key`
+/// field when the event has one, and a field per attribute, plus
+/// `dedup_id` when the source gives one. The id of the entry is
+/// returned as the event id. Nothing is sent to Redis until the future
+/// of `publish` is polled.
+pub struct RedisSink {
+    connection: ConnectionManager,
+    config: RedisConfig,
+    // Streams whose consumer group is known to exist.
+    grouped: Arc<Mutex<HashSet<String>>>,
+}
+
+impl RedisSink {
+    pub async fn connect(config: RedisConfig) -> Result<Self> {
+        let client = redis::Client::open(config.url.as_str())
+            .context("Invalid Redis URL")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(RedisSink {
+            connection,
+            config,
+            grouped: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    pub async fn from_env() -> Result<Self> {
+        RedisSink::connect(
+            envy::prefixed("REDIS_").from_env::<RedisConfig>()?,
+        )
+        .await
+    }
+}
+
+impl EventSink for RedisSink {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        let mut connection = self.connection.clone();
+        let stream =
+            format!("{}{}", self.config.stream_prefix, event.source);
+        let group = self.config.group.clone();
+        let maxlen = self.config.maxlen;
+        let grouped = self.grouped.clone();
+
+        let mut fields: Vec<(String, Vec<u8>)> =
+            vec![("payload".to_string(), event.payload.clone())];
+        if let Some(key) = &event.ordering_key {
+            fields.push((
+                "ordering_key".to_string(),
+                key.clone().into_bytes(),
+            ));
+        }
+        fields.extend(event.message_attributes().iter().map(
+            |(key, value)| (key.clone(), value.clone().into_bytes()),
+        ));
+
+        async move {
+            if let Some(group) = group {
+                if !grouped.lock().unwrap().contains(&stream) {
+                    create_group(&mut connection, &stream, &group)
+                        .await?;
+                    grouped.lock().unwrap().insert(stream.clone());
+                }
+            }
+
+            let id: String = match maxlen {
+                Some(maxlen) => {
+                    connection
+                        .xadd_maxlen(
+                            &stream,
+                            StreamMaxlen::Approx(maxlen),
+                            "*",
+                            &fields,
+                        )
+                        .await?
+                }
+                None => {
+                    connection.xadd(&stream, "*", &fields).await?
+                }
+            };
+            Ok(id)
+        }
+        .boxed()
+    }
+}
+
+async fn create_group(
+    connection: &mut ConnectionManager,
+    stream: &str,
+    group: &str,
+) -> Result<()> {
+    let created: RedisResult<()> =
+        connection.xgroup_create_mkstream(stream, group, "$").await;
+    match created {
+        Err(e) if e.code() != Some("BUSYGROUP") => Err(e)
+            .with_context(|| {
+                format!(
+                    "Failed to create consumer group {} on {}",
+                    group, stream
+                )
+            }),
+        // Created now, or earlier by another instance.
+        _ => Ok(()),
+    }
+}
+
+fn default_url() -> String {
+    "redis://localhost:6379".to_string()
+}
+
+fn default_stream_prefix() -> String {
+    "ingest:".to_string()
+}
+
+/// Runs against the server of `docker-compose.yml`:
+/// `docker compose up -d redis && cargo test --features redis -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use redis::streams::{StreamReadOptions, StreamReadReply};
+    use redis::Value;
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn url() -> String {
+        std::env::var("REDIS_URL").unwrap_or_else(|_| default_url())
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Redis server"]
+    async fn adds_trimmed_entries_for_the_group() {
+        let prefix = format!("ingest-test-{}:", std::process::id());
+        let sink = RedisSink::connect(RedisConfig {
+            url: url(),
+            stream_prefix: prefix.clone(),
+            maxlen: Some(1),
+            group: Some("archive".to_string()),
+        })
+        .await
+        .unwrap();
+
+        let event = IngestedEvent {
+            source: "todoist".to_string(),
+            topic: "todoist".to_string(),
+            payload: b"{\"event_name\":\"item:added\"}".to_vec(),
+            attributes: HashMap::from([(
+                "event_name".to_string(),
+                "item:added".to_string(),
+            )]),
+            ordering_key: Some("100".to_string()),
+            dedup_id: None,
+            received_at: time::OffsetDateTime::now_utc(),
+        };
+        let id = sink.publish(&event).await.unwrap();
+
+        let stream = format!("{}todoist", prefix);
+        let mut connection = sink.connection.clone();
+        let reply: StreamReadReply = connection
+            .xread_options(
+                &[&stream],
+                &[">"],
+                &StreamReadOptions::default()
+                    .group("archive", "test"),
+            )
+            .await
+            .unwrap();
+        let entry = &reply.keys[0].ids[0];
+        assert_eq!(entry.id, id);
+        assert_eq!(
+            entry.map["payload"],
+            Value::Data(event.payload.clone())
+        );
+        assert_eq!(
+            entry.map["ordering_key"],
+            Value::Data(b"100".to_vec())
+        );
+        assert_eq!(
+            entry.map["event_name"],
+            Value::Data(b"item:added".to_vec())
+        );
+
+        for _ in 0..200 {
+            sink.publish(&event).await.unwrap();
+        }
+        let len: usize = connection.xlen(&stream).await.unwrap();
+        assert!(len < 201, "stream not trimmed");
+
+        let _: () = connection.del(&stream).await.unwrap();
+    }
+}
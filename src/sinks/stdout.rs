@@ -0,0 +1,34 @@
+use anyhow::Result;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{envelope, EventSink};
+use crate::event::IngestedEvent;
+
+/// Writes the JSON envelope of each event to stdout, one per line.
+///
+/// Meant for local runs, the ids are line numbers starting at 1.
+#[derive(Default)]
+pub struct StdoutSink {
+    lines: AtomicU64,
+}
+
+impl EventSink for StdoutSink {
+    fn publish(
+        &self,
+        event: &IngestedEvent,
+    ) -> BoxFuture<'static, Result<String>> {
+        let written = envelope::to_json(event)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(&line)?;
+                stdout.write_all(b"\n")?;
+                stdout.flush()?;
+                Ok(self.lines.fetch_add(1, Ordering::SeqCst) + 1)
+            })
+            .map(|line| line.to_string());
+        future::ready(written).boxed()
+    }
+}